@@ -0,0 +1,19 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use metrics_exporter_wasm_core::{
+    PayloadDecoder,
+    RecordedEvent,
+    PAYLOAD_CONTENT_TYPE_V0,
+    PAYLOAD_CONTENT_TYPE_V1,
+};
+
+fuzz_target!(|input: (bool, u8, &[u8])| {
+    let (v1, encoding, data) = input;
+    let content_type = if v1 { PAYLOAD_CONTENT_TYPE_V1 } else { PAYLOAD_CONTENT_TYPE_V0 };
+    let content_encoding = ["br", "gzip", "deflate", "zstd", "identity"][encoding as usize % 5];
+    let decoder = PayloadDecoder::new().max_decompressed_size(1024 * 1024);
+    if let Ok(events) = decoder.decode(Some(content_type), Some(content_encoding), data) {
+        let _ = Vec::<RecordedEvent>::try_from(events);
+    }
+});
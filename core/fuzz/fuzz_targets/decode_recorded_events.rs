@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use metrics_exporter_wasm_core::{
+    Asn1Decode as _,
+    DecodeLimits,
+    Events,
+    ExporterConfig,
+    PayloadVersion,
+    RecordedEvent,
+    RecordedEvents,
+};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(events) = RecordedEvents::decode(data) {
+        let _ = Vec::<RecordedEvent>::try_from(events);
+    }
+    if let Ok(events) = Events::decode(data) {
+        let _ = Vec::<metrics_exporter_wasm_core::Event>::try_from(events);
+    }
+    if let Ok(events) = PayloadVersion::V1.decode(data, &DecodeLimits::default()) {
+        let _ = Vec::<RecordedEvent>::try_from(events);
+    }
+    let _ = ExporterConfig::decode(data);
+});
@@ -1,15 +1,21 @@
-use super::generated;
+use super::{
+    generated,
+    Error,
+    Result,
+};
 use crate::Event;
 use metrics::Key;
 
-impl From<generated::Event> for Event {
-    fn from(value: generated::Event) -> Self {
+impl TryFrom<generated::Event> for Event {
+    type Error = Error;
+
+    fn try_from(value: generated::Event) -> Result<Self> {
         use generated::{
             EventDescription,
             EventMetric,
             EventMetricKey,
         };
-        match value {
+        Ok(match value {
             generated::Event::Description(EventDescription {
                 key_name: name,
                 metric_type,
@@ -31,9 +37,12 @@ impl From<generated::Event> for Event {
                     .map(|entry| metrics::Label::new(entry.key, entry.value))
                     .collect::<Vec<_>>();
                 let key = Key::from_parts(name, labels);
-                Event::Metric { key, op: op.into() }
+                Event::Metric {
+                    key,
+                    op: op.try_into()?,
+                }
             }
-        }
+        })
     }
 }
 
@@ -1,6 +1,7 @@
 use super::{
     Asn1Decode,
     Asn1Encode,
+    DecodeLimits,
     Error,
     Events,
     Result,
@@ -21,17 +22,25 @@ impl Asn1Encode for Events {
 
 impl Asn1Decode for Events {
     /// Deserialize from asn1.
-    fn decode(data: &[u8]) -> Result<Self> {
+    fn decode_with_limits(data: &[u8], limits: &DecodeLimits) -> Result<Self> {
+        limits.check_input(data)?;
         let mut reader = UperReader::from(Bits::from(data));
-        reader
+        let events = reader
             .read::<Events>()
-            .map_err(|e| Error::new(std::io::ErrorKind::InvalidData, e))
+            .map_err(|e| Error::new(std::io::ErrorKind::InvalidData, e))?;
+        limits.check_events(events.0.len())?;
+        for event in &events.0 {
+            limits.check_event(event)?;
+        }
+        Ok(events)
     }
 }
 
-impl From<Events> for Vec<Event> {
-    fn from(value: Events) -> Self {
-        value.0.into_iter().map(Event::from).collect()
+impl TryFrom<Events> for Vec<Event> {
+    type Error = Error;
+
+    fn try_from(value: Events) -> Result<Self> {
+        value.0.into_iter().map(Event::try_from).collect()
     }
 }
 
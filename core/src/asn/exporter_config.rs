@@ -0,0 +1,165 @@
+use super::{
+    generated,
+    Asn1Decode,
+    Asn1Encode,
+    DecodeLimits,
+    Error,
+    Result,
+};
+use crate::{
+    ExporterConfig,
+    PayloadCompression,
+    SamplingRule,
+};
+use asn1rs::prelude::*;
+use std::time::Duration;
+
+impl From<generated::ExporterConfig> for ExporterConfig {
+    fn from(value: generated::ExporterConfig) -> Self {
+        let generated::ExporterConfig {
+            send_frequency_ms,
+            sampling,
+            disabled_metrics,
+            compression,
+        } = value;
+        Self {
+            send_frequency: send_frequency_ms.map(|ms| Duration::from_millis(ms as u64)),
+            sampling: sampling
+                .into_iter()
+                .map(|rule| SamplingRule {
+                    prefix: rule.prefix,
+                    rate: rule.rate_per_mille as f64 / 1000.0,
+                })
+                .collect(),
+            disabled_metrics,
+            compression: compression.map(Into::into),
+        }
+    }
+}
+
+impl From<ExporterConfig> for generated::ExporterConfig {
+    fn from(value: ExporterConfig) -> Self {
+        let ExporterConfig {
+            send_frequency,
+            sampling,
+            disabled_metrics,
+            compression,
+        } = value;
+        Self {
+            send_frequency_ms: send_frequency.map(|freq| freq.as_millis().min(u32::MAX as u128) as u32),
+            sampling: sampling
+                .into_iter()
+                .map(|rule| generated::ExporterConfigSampling {
+                    prefix: rule.prefix,
+                    rate_per_mille: (rule.rate.clamp(0.0, 1.0) * 1000.0).round() as u16,
+                })
+                .collect(),
+            disabled_metrics,
+            compression: compression.map(Into::into),
+        }
+    }
+}
+
+impl From<generated::PayloadCompression> for PayloadCompression {
+    fn from(value: generated::PayloadCompression) -> Self {
+        match value {
+            generated::PayloadCompression::None => PayloadCompression::None,
+            generated::PayloadCompression::Brotli => PayloadCompression::Brotli,
+            generated::PayloadCompression::Zstd => PayloadCompression::Zstd,
+        }
+    }
+}
+
+impl From<PayloadCompression> for generated::PayloadCompression {
+    fn from(value: PayloadCompression) -> Self {
+        match value {
+            PayloadCompression::None => generated::PayloadCompression::None,
+            PayloadCompression::Brotli => generated::PayloadCompression::Brotli,
+            PayloadCompression::Zstd => generated::PayloadCompression::Zstd,
+        }
+    }
+}
+
+impl Asn1Encode for ExporterConfig {
+    /// Serialize the config using asn1.
+    fn encode(&self) -> Result<Vec<u8>> {
+        let config = generated::ExporterConfig::from(self.clone());
+        let mut writer = UperWriter::default();
+        writer
+            .write(&config)
+            .map_err(|e| Error::new(std::io::ErrorKind::InvalidData, e))?;
+        Ok(writer.into_bytes_vec())
+    }
+}
+
+impl Asn1Decode for ExporterConfig {
+    /// Deserialize from asn1.
+    fn decode_with_limits(data: &[u8], limits: &DecodeLimits) -> Result<Self> {
+        limits.check_input(data)?;
+        let mut reader = UperReader::from(Bits::from(data));
+        let config = reader
+            .read::<generated::ExporterConfig>()
+            .map_err(|e| Error::new(std::io::ErrorKind::InvalidData, e))?;
+        limits.check_list(config.sampling.len())?;
+        limits.check_list(config.disabled_metrics.len())?;
+        for sampling in &config.sampling {
+            limits.check_string(&sampling.prefix)?;
+        }
+        for name in &config.disabled_metrics {
+            limits.check_string(name)?;
+        }
+        Ok(config.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exporter_config_roundtrip() {
+        let configs = [
+            ExporterConfig::default(),
+            ExporterConfig {
+                send_frequency: Some(Duration::from_millis(2500)),
+                sampling: vec![
+                    SamplingRule {
+                        prefix: "frame_".to_string(),
+                        rate: 0.25,
+                    },
+                    SamplingRule {
+                        prefix: "".to_string(),
+                        rate: 1.0,
+                    },
+                ],
+                disabled_metrics: vec!["debug_counter".to_string()],
+                compression: Some(PayloadCompression::Zstd),
+            },
+        ];
+        for config in configs {
+            let bytes = config.encode().unwrap();
+            assert_eq!(ExporterConfig::decode(&bytes).unwrap(), config);
+        }
+    }
+
+    #[test]
+    fn exporter_config_list_limit() {
+        let config = ExporterConfig {
+            disabled_metrics: vec!["a".to_string(), "b".to_string()],
+            ..Default::default()
+        };
+        let bytes = config.encode().unwrap();
+        let limits = DecodeLimits {
+            max_list_entries: 2,
+            // The lists don't count as events.
+            max_events: 1,
+            ..DecodeLimits::default()
+        };
+        assert!(ExporterConfig::decode_with_limits(&bytes, &limits).is_ok());
+        let limits = DecodeLimits {
+            max_list_entries: 1,
+            ..limits
+        };
+        assert!(ExporterConfig::decode_with_limits(&bytes, &limits).is_err());
+    }
+}
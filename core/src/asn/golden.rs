@@ -0,0 +1,312 @@
+//! Golden payloads that pin the wire format, see `fixtures/README.md`. Every fixture must decode into the expected
+//! events and re-encode byte for byte, a failure means that deployed clients or servers would break.
+
+use super::{
+    generated,
+    DecodeLimits,
+    PayloadVersion,
+};
+use crate::{
+    ClientSession,
+    Event,
+    MetricOperation,
+    MetricType,
+    PayloadMetadata,
+    RecordedEvent,
+};
+use chrono::prelude::*;
+use metrics::{
+    Key,
+    Label,
+    Unit,
+};
+
+struct Fixture {
+    name: &'static str,
+    version: PayloadVersion,
+    bytes: &'static [u8],
+    events: fn() -> Vec<RecordedEvent>,
+    session: fn() -> Option<ClientSession>,
+    sequence: Option<u64>,
+    nonce: Option<&'static str>,
+}
+
+const FIXTURES: &[Fixture] = &[
+    Fixture {
+        name: "v0_descriptions",
+        version: PayloadVersion::V0,
+        bytes: include_bytes!("fixtures/v0_descriptions.bin"),
+        events: descriptions,
+        session: no_session,
+        sequence: None,
+        nonce: None,
+    },
+    Fixture {
+        name: "v0_operations",
+        version: PayloadVersion::V0,
+        bytes: include_bytes!("fixtures/v0_operations.bin"),
+        events: operations,
+        session: no_session,
+        sequence: None,
+        nonce: None,
+    },
+    Fixture {
+        name: "v0_many_events",
+        version: PayloadVersion::V0,
+        bytes: include_bytes!("fixtures/v0_many_events.bin"),
+        events: many_events,
+        session: no_session,
+        sequence: None,
+        nonce: None,
+    },
+    Fixture {
+        name: "v1_descriptions",
+        version: PayloadVersion::V1,
+        bytes: include_bytes!("fixtures/v1_descriptions.bin"),
+        events: descriptions,
+        session: no_session,
+        sequence: None,
+        nonce: None,
+    },
+    Fixture {
+        name: "v1_operations",
+        version: PayloadVersion::V1,
+        bytes: include_bytes!("fixtures/v1_operations.bin"),
+        events: operations,
+        session: no_session,
+        sequence: None,
+        nonce: None,
+    },
+    Fixture {
+        name: "v1_many_events",
+        version: PayloadVersion::V1,
+        bytes: include_bytes!("fixtures/v1_many_events.bin"),
+        events: many_events,
+        session: no_session,
+        sequence: None,
+        nonce: None,
+    },
+    Fixture {
+        name: "v1_session",
+        version: PayloadVersion::V1,
+        bytes: include_bytes!("fixtures/v1_session.bin"),
+        events: || operations()[..2].to_vec(),
+        session: client_session,
+        sequence: None,
+        nonce: None,
+    },
+    Fixture {
+        name: "v1_sequence",
+        version: PayloadVersion::V1,
+        bytes: include_bytes!("fixtures/v1_sequence.bin"),
+        events: || operations()[..2].to_vec(),
+        session: client_session,
+        sequence: Some(7),
+        nonce: Some("a1b2c3d4"),
+    },
+];
+
+impl Fixture {
+    fn metadata(&self) -> Option<PayloadMetadata> {
+        Some(PayloadMetadata {
+            session_id: (self.session)()?.session_id?,
+            nonce: self.nonce.unwrap_or_default().to_string(),
+            sequence: self.sequence?,
+        })
+    }
+}
+
+fn recording_started_at() -> DateTime<Utc> {
+    Utc.timestamp_opt(1_714_564_800, 123_456_789).unwrap()
+}
+
+fn event(offset_ms: u32, event: Event) -> RecordedEvent {
+    RecordedEvent {
+        timestamp: recording_started_at() + chrono::Duration::milliseconds(offset_ms.into()),
+        event,
+    }
+}
+
+fn description(
+    offset_ms: u32,
+    name: &'static str,
+    metric_type: MetricType,
+    unit: Option<Unit>,
+    text: &'static str,
+) -> RecordedEvent {
+    event(
+        offset_ms,
+        Event::Description {
+            name: name.into(),
+            metric_type,
+            unit,
+            description: text.into(),
+        },
+    )
+}
+
+fn metric(
+    offset_ms: u32,
+    name: &'static str,
+    labels: &[(&'static str, &'static str)],
+    op: MetricOperation,
+) -> RecordedEvent {
+    let labels = labels
+        .iter()
+        .map(|(key, value)| Label::new(*key, *value))
+        .collect::<Vec<_>>();
+    event(
+        offset_ms,
+        Event::Metric {
+            key: Key::from_parts(name, labels),
+            op,
+        },
+    )
+}
+
+fn descriptions() -> Vec<RecordedEvent> {
+    vec![
+        description(
+            0,
+            "requests",
+            MetricType::Counter,
+            Some(Unit::Count),
+            "Number of requests",
+        ),
+        description(1, "queue_length", MetricType::Gauge, None, ""),
+        description(
+            2,
+            "latency",
+            MetricType::Histogram,
+            Some(Unit::Milliseconds),
+            "Request latency",
+        ),
+        description(
+            3,
+            "throughput",
+            MetricType::Gauge,
+            Some(Unit::CountPerSecond),
+            "Größe pro Sekunde",
+        ),
+    ]
+}
+
+fn operations() -> Vec<RecordedEvent> {
+    vec![
+        metric(0, "requests", &[], MetricOperation::IncrementCounter(1)),
+        metric(
+            5,
+            "requests",
+            &[("method", "GET"), ("status", "200")],
+            MetricOperation::IncrementCounter(42),
+        ),
+        metric(17, "total", &[], MetricOperation::SetCounter(100)),
+        metric(
+            250,
+            "queue_length",
+            &[("queue", "größe")],
+            MetricOperation::IncrementGauge(2.5),
+        ),
+        metric(
+            1000,
+            "queue_length",
+            &[("queue", "größe")],
+            MetricOperation::DecrementGauge(0.75),
+        ),
+        metric(65536, "temperature", &[], MetricOperation::SetGauge(-12.125)),
+        metric(
+            u32::MAX,
+            "latency",
+            &[("route", "/api/v1")],
+            MetricOperation::RecordHistogram(0.1),
+        ),
+    ]
+}
+
+fn no_session() -> Option<ClientSession> {
+    None
+}
+
+fn client_session() -> Option<ClientSession> {
+    Some(
+        ClientSession::new()
+            .session_id("4f1c2a9e-session")
+            .client_instance_id("b7d3-instance")
+            .app_version("1.2.3")
+            .user_agent("Firefox 131 / Linux")
+            .resource_attribute("page", "/dashboard")
+            .resource_attribute("env", "production"),
+    )
+}
+
+/// More than 127 events, so the length of the event list takes two bytes.
+fn many_events() -> Vec<RecordedEvent> {
+    (0..200)
+        .map(|i| {
+            metric(
+                i,
+                "ticks",
+                &[],
+                MetricOperation::IncrementCounter(u64::from(i % 127) + 1),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn fixtures_decode() {
+    for fixture in FIXTURES {
+        let payload = fixture
+            .version
+            .decode_with_session(fixture.bytes, &DecodeLimits::default())
+            .unwrap_or_else(|err| panic!("failed to decode {}: {err}", fixture.name));
+        assert_eq!(payload.session, (fixture.session)(), "{}", fixture.name);
+        assert_eq!(payload.sequence, fixture.sequence, "{}", fixture.name);
+        assert_eq!(payload.sequence_nonce.as_deref(), fixture.nonce, "{}", fixture.name);
+        let events = Vec::<RecordedEvent>::try_from(payload.events)
+            .unwrap_or_else(|err| panic!("failed to convert {}: {err}", fixture.name));
+        assert_eq!(events, (fixture.events)(), "{}", fixture.name);
+    }
+}
+
+#[test]
+fn fixtures_reencode() {
+    for fixture in FIXTURES {
+        let decoded = fixture
+            .version
+            .decode_with_session(fixture.bytes, &DecodeLimits::default())
+            .unwrap_or_else(|err| panic!("failed to decode {}: {err}", fixture.name));
+        let bytes = fixture
+            .version
+            .encode_with_sequence(
+                &decoded.events,
+                decoded.session.as_ref(),
+                PayloadMetadata::from_payload(&decoded).as_ref(),
+            )
+            .unwrap();
+        assert_eq!(bytes, fixture.bytes, "{}: re-encoding decoded payload", fixture.name);
+
+        let events = generated::RecordedEvents::new(recording_started_at(), (fixture.events)());
+        let bytes = fixture
+            .version
+            .encode_with_sequence(&events, (fixture.session)().as_ref(), fixture.metadata().as_ref())
+            .unwrap();
+        assert_eq!(bytes, fixture.bytes, "{}: encoding expected events", fixture.name);
+    }
+}
+
+/// Rewrites the version 1 fixtures with the current encoder, run with `just regenerate-v1-fixtures` after changing the
+/// unreleased version 1 schema. The version 0 fixtures are never regenerated.
+#[test]
+#[ignore]
+fn regenerate_v1_fixtures() {
+    let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/asn/fixtures");
+    for fixture in FIXTURES.iter().filter(|fixture| fixture.version == PayloadVersion::V1) {
+        let events = generated::RecordedEvents::new(recording_started_at(), (fixture.events)());
+        let bytes = fixture
+            .version
+            .encode_with_sequence(&events, (fixture.session)().as_ref(), fixture.metadata().as_ref())
+            .unwrap();
+        std::fs::write(dir.join(format!("{}.bin", fixture.name)), bytes).unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+use super::{
+    generated,
+    Error,
+    Result,
+};
+
+/// Limits for decoding untrusted input with [super::Asn1Decode::decode_with_limits]. Payloads exceeding a limit are
+/// rejected with an [std::io::ErrorKind::InvalidData] error. [super::Asn1Decode::decode] uses the default limits.
+///
+/// Only [Self::max_input_size] is checked before decoding. The other limits are checked once the input is fully
+/// decoded, so they keep oversized events away from the application but don't bound the work and memory of decoding
+/// itself. That is bounded by the input size only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Maximum size in bytes of the encoded input.
+    pub max_input_size: usize,
+    /// Maximum number of events in a payload.
+    pub max_events: usize,
+    /// Maximum number of entries of each list of an [crate::ExporterConfig].
+    pub max_list_entries: usize,
+    /// Maximum length in bytes of metric names, label keys and values, descriptions and other strings.
+    pub max_string_length: usize,
+    /// Maximum number of labels of a metric key and of resource attributes of a [crate::ClientSession].
+    pub max_labels: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_input_size: 16 * 1024 * 1024,
+            max_events: 100_000,
+            max_list_entries: 1024,
+            max_string_length: 4096,
+            max_labels: 64,
+        }
+    }
+}
+
+impl DecodeLimits {
+    /// No limits besides the size of the input.
+    pub const UNLIMITED: Self = Self {
+        max_input_size: usize::MAX,
+        max_events: usize::MAX,
+        max_list_entries: usize::MAX,
+        max_string_length: usize::MAX,
+        max_labels: usize::MAX,
+    };
+
+    pub(super) fn check_input(&self, data: &[u8]) -> Result<()> {
+        if data.len() > self.max_input_size {
+            return Err(invalid(format!(
+                "input of {} bytes exceeds the limit of {}",
+                data.len(),
+                self.max_input_size
+            )));
+        }
+        Ok(())
+    }
+
+    pub(super) fn check_list(&self, n: usize) -> Result<()> {
+        if n > self.max_list_entries {
+            return Err(invalid(format!(
+                "list of {n} entries exceeds the limit of {}",
+                self.max_list_entries
+            )));
+        }
+        Ok(())
+    }
+
+    pub(super) fn check_events(&self, n: usize) -> Result<()> {
+        if n > self.max_events {
+            return Err(invalid(format!("{n} events exceed the limit of {}", self.max_events)));
+        }
+        Ok(())
+    }
+
+    pub(super) fn check_string(&self, s: &str) -> Result<()> {
+        if s.len() > self.max_string_length {
+            return Err(invalid(format!(
+                "string of {} bytes exceeds the limit of {}",
+                s.len(),
+                self.max_string_length
+            )));
+        }
+        Ok(())
+    }
+
+    pub(super) fn check_recorded_events(&self, events: &generated::RecordedEvents) -> Result<()> {
+        self.check_events(events.events.len())?;
+        for event in &events.events {
+            self.check_event(&event.event)?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn check_event(&self, event: &generated::Event) -> Result<()> {
+        match event {
+            generated::Event::Description(description) => {
+                self.check_string(&description.key_name)?;
+                self.check_string(&description.description)?;
+            }
+            generated::Event::Metric(metric) => {
+                self.check_string(&metric.key.name)?;
+                self.check_labels(metric.key.label.len())?;
+                for label in &metric.key.label {
+                    self.check_string(&label.key)?;
+                    self.check_string(&label.value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn check_session(&self, session: &generated::ClientSession) -> Result<()> {
+        let strings = [
+            &session.session_id,
+            &session.client_instance_id,
+            &session.app_version,
+            &session.user_agent,
+        ];
+        for s in strings.into_iter().flatten() {
+            self.check_string(s)?;
+        }
+        self.check_labels(session.resource_attributes.len())?;
+        for attribute in &session.resource_attributes {
+            self.check_string(&attribute.key)?;
+            self.check_string(&attribute.value)?;
+        }
+        Ok(())
+    }
+
+    fn check_labels(&self, n: usize) -> Result<()> {
+        if n > self.max_labels {
+            return Err(invalid(format!("{n} labels exceed the limit of {}", self.max_labels)));
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn invalid(msg: impl Into<String>) -> Error {
+    Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
@@ -1,21 +1,35 @@
-use super::generated;
+use super::{
+    generated,
+    limits::invalid,
+    Error,
+    Result,
+};
 use crate::MetricOperation;
 
 #[rustfmt::skip]
-impl From<generated::MetricOperation> for MetricOperation {
-    fn from(value: generated::MetricOperation) -> Self {
+impl TryFrom<generated::MetricOperation> for MetricOperation {
+    type Error = Error;
+
+    fn try_from(value: generated::MetricOperation) -> Result<Self> {
         use generated::MetricOperation::*;
-        match value {
-            IncrementCounter(val) => MetricOperation::IncrementCounter(val as _),
-            SetCounter(val) => MetricOperation::SetCounter(val as _),
-            IncrementGauge(val) => MetricOperation::IncrementGauge(f64::from_be_bytes(val.try_into().unwrap())),
-            DecrementGauge(val) => MetricOperation::DecrementGauge(f64::from_be_bytes(val.try_into().unwrap())),
-            SetGauge(val) => MetricOperation::SetGauge(f64::from_be_bytes(val.try_into().unwrap())),
-            RecordHistogram(val) => MetricOperation::RecordHistogram(f64::from_be_bytes(val.try_into().unwrap())),
-        }
+        Ok(match value {
+            IncrementCounter(val) => MetricOperation::IncrementCounter(val),
+            SetCounter(val) => MetricOperation::SetCounter(val),
+            IncrementGauge(val) => MetricOperation::IncrementGauge(f64_from_be_bytes(&val)?),
+            DecrementGauge(val) => MetricOperation::DecrementGauge(f64_from_be_bytes(&val)?),
+            SetGauge(val) => MetricOperation::SetGauge(f64_from_be_bytes(&val)?),
+            RecordHistogram(val) => MetricOperation::RecordHistogram(f64_from_be_bytes(&val)?),
+        })
     }
 }
 
+fn f64_from_be_bytes(bytes: &[u8]) -> Result<f64> {
+    let bytes = bytes
+        .try_into()
+        .map_err(|_| invalid(format!("expected an 8 byte float, got {} bytes", bytes.len())))?;
+    Ok(f64::from_be_bytes(bytes))
+}
+
 #[rustfmt::skip]
 impl From<MetricOperation> for generated::MetricOperation {
     fn from(value: MetricOperation) -> Self {
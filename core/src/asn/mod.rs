@@ -1,15 +1,30 @@
 mod event;
 mod events;
+mod exporter_config;
+#[cfg(test)]
+mod golden;
+mod limits;
 mod metric_operation;
 mod metric_type;
+mod payload;
 mod recorded_event;
+mod session;
+pub mod transfer;
 mod unit;
 
 pub use generated::{
     Events,
     RecordedEvents,
 };
+pub use limits::DecodeLimits;
+pub use payload::{
+    DecodedPayload,
+    PayloadVersion,
+    PAYLOAD_CONTENT_TYPE_V0,
+    PAYLOAD_CONTENT_TYPE_V1,
+};
 
+#[allow(dead_code)]
 mod generated {
     include!(concat!(env!("OUT_DIR"), "/metrics.rs"));
 }
@@ -22,7 +37,12 @@ pub trait Asn1Encode {
 }
 
 pub trait Asn1Decode: Sized {
-    fn decode(data: &[u8]) -> Result<Self>;
+    /// Decode `data` with the default [DecodeLimits].
+    fn decode(data: &[u8]) -> Result<Self> {
+        Self::decode_with_limits(data, &DecodeLimits::default())
+    }
+
+    fn decode_with_limits(data: &[u8], limits: &DecodeLimits) -> Result<Self>;
 }
 
 #[cfg(test)]
@@ -30,6 +50,7 @@ mod tests {
     use crate::{
         Asn1Decode,
         Asn1Encode,
+        DecodeLimits,
         Event,
         Events,
         MetricOperation,
@@ -50,4 +71,84 @@ mod tests {
         let events2 = Events::decode(&bytes).unwrap();
         assert_eq!(events, events2);
     }
+
+    #[test]
+    fn malformed_gauge_is_an_error() {
+        use super::generated;
+
+        let event = generated::Event::Metric(generated::EventMetric {
+            key: generated::EventMetricKey {
+                name: "gauge".to_string(),
+                label: Vec::new(),
+            },
+            op: generated::MetricOperation::SetGauge(vec![1, 2, 3]),
+        });
+        assert!(Event::try_from(event).is_err());
+    }
+
+    #[test]
+    fn out_of_range_timestamp_is_an_error() {
+        use super::generated;
+
+        let events = generated::RecordedEvents {
+            recording_started_at: generated::Timestamp {
+                seconds: u64::MAX,
+                nanos: 0,
+            },
+            events: Vec::new(),
+        };
+        assert!(Vec::<crate::RecordedEvent>::try_from(events).is_err());
+    }
+
+    #[test]
+    fn decode_limits() {
+        let event = Event::Metric {
+            key: Key::from_parts("some-key", &[("a", "1"), ("b", "2")]),
+            op: MetricOperation::IncrementCounter(1),
+        };
+        let bytes = Events::from(vec![event.clone(), event]).encode().unwrap();
+
+        let limits = DecodeLimits::default();
+        assert!(Events::decode_with_limits(&bytes, &limits).is_ok());
+        let max_events = DecodeLimits {
+            max_events: 1,
+            ..limits
+        };
+        assert!(Events::decode_with_limits(&bytes, &max_events).is_err());
+        let max_labels = DecodeLimits {
+            max_labels: 1,
+            ..limits
+        };
+        assert!(Events::decode_with_limits(&bytes, &max_labels).is_err());
+        let max_string_length = DecodeLimits {
+            max_string_length: 4,
+            ..limits
+        };
+        assert!(Events::decode_with_limits(&bytes, &max_string_length).is_err());
+        let max_input_size = DecodeLimits {
+            max_input_size: bytes.len() - 1,
+            ..limits
+        };
+        assert!(Events::decode_with_limits(&bytes, &max_input_size).is_err());
+    }
+
+    #[test]
+    fn payload_version_from_content_type() {
+        use super::PayloadVersion;
+        assert_eq!(PayloadVersion::from_content_type(None).unwrap(), PayloadVersion::V0);
+        assert_eq!(
+            PayloadVersion::from_content_type(Some("application/octet-stream")).unwrap(),
+            PayloadVersion::V0
+        );
+        assert_eq!(
+            PayloadVersion::from_content_type(Some("application/vnd.metrics-exporter-wasm.v1+uper; charset=binary"))
+                .unwrap(),
+            PayloadVersion::V1
+        );
+        assert!(PayloadVersion::from_content_type(Some("application/json")).is_err());
+        assert_eq!(
+            PayloadVersion::from_websocket_protocol(PayloadVersion::V1.websocket_protocol()).unwrap(),
+            PayloadVersion::V1
+        );
+    }
 }
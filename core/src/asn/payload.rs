@@ -0,0 +1,556 @@
+use super::{
+    generated,
+    limits::invalid,
+    DecodeLimits,
+    Error,
+    Result,
+};
+use crate::{
+    ClientSession,
+    PayloadMetadata,
+};
+use asn1rs::prelude::*;
+
+/// The version of the payload format. Version 0 is the unversioned [generated::RecordedEvents] encoding, later versions
+/// are wrapped in the extensible `MetricsPayload` envelope. The version is signaled with the content type, see
+/// [PayloadVersion::content_type].
+///
+/// Servers should decode every version with [PayloadVersion::from_content_type] and [PayloadVersion::decode] (or
+/// [crate::decode_payload]), so that they keep accepting payloads of older clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PayloadVersion {
+    V0,
+    V1,
+}
+
+/// The content type of version 0 payloads.
+pub const PAYLOAD_CONTENT_TYPE_V0: &str = "application/octet-stream";
+
+/// The content type of version 1 payloads.
+pub const PAYLOAD_CONTENT_TYPE_V1: &str = "application/vnd.metrics-exporter-wasm.v1+uper";
+
+impl PayloadVersion {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::V0 => PAYLOAD_CONTENT_TYPE_V0,
+            Self::V1 => PAYLOAD_CONTENT_TYPE_V1,
+        }
+    }
+
+    /// The version of a payload with `content_type`. Payloads without a content type are version 0, as sent by clients
+    /// that predate versioning. Parameters like `; charset=...` are ignored.
+    pub fn from_content_type(content_type: Option<&str>) -> Result<Self> {
+        let Some(content_type) = content_type else {
+            return Ok(Self::V0);
+        };
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        if mime.eq_ignore_ascii_case(PAYLOAD_CONTENT_TYPE_V0) {
+            Ok(Self::V0)
+        } else if mime.eq_ignore_ascii_case(PAYLOAD_CONTENT_TYPE_V1) {
+            Ok(Self::V1)
+        } else {
+            Err(Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("unsupported payload content type {content_type:?}"),
+            ))
+        }
+    }
+
+    /// The WebSocket subprotocol that signals this version, WebSocket frames have no content type. Version 0 uses no
+    /// subprotocol.
+    pub fn websocket_protocol(self) -> Option<&'static str> {
+        match self {
+            Self::V0 => None,
+            Self::V1 => Some("metrics-exporter-wasm.v1"),
+        }
+    }
+
+    /// The version of a WebSocket connection with the negotiated subprotocol `protocol`.
+    pub fn from_websocket_protocol(protocol: Option<&str>) -> Result<Self> {
+        match protocol {
+            None | Some("") => Ok(Self::V0),
+            Some(protocol) if Some(protocol) == Self::V1.websocket_protocol() => Ok(Self::V1),
+            Some(protocol) => Err(Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("unsupported websocket protocol {protocol:?}"),
+            )),
+        }
+    }
+
+    /// Encode `events` in this version.
+    pub fn encode(self, events: &generated::RecordedEvents) -> Result<Vec<u8>> {
+        self.encode_with_session(events, None)
+    }
+
+    /// Encode `events` together with the `session` of the client. Version 0 has no session, it is dropped.
+    pub fn encode_with_session(
+        self,
+        events: &generated::RecordedEvents,
+        session: Option<&ClientSession>,
+    ) -> Result<Vec<u8>> {
+        self.encode_with_sequence(events, session, None)
+    }
+
+    /// Encode `events` together with the `session` of the client and the sequence number and nonce of `metadata`, see
+    /// [crate::SequenceTracker]. The session id is part of the `session`. Version 0 has neither, they are dropped.
+    pub fn encode_with_sequence(
+        self,
+        events: &generated::RecordedEvents,
+        session: Option<&ClientSession>,
+        metadata: Option<&PayloadMetadata>,
+    ) -> Result<Vec<u8>> {
+        let mut writer = UperWriter::default();
+        let result = match self {
+            Self::V0 => writer.write(events),
+            Self::V1 => {
+                let mut events = generated::RecordedEventsV1::from(events.clone());
+                events.session = session.cloned().map(Into::into);
+                events.sequence = metadata.map(|metadata| metadata.sequence);
+                events.sequence_nonce = metadata
+                    .map(|metadata| metadata.nonce.clone())
+                    .filter(|nonce| !nonce.is_empty());
+                writer.write(&generated::MetricsPayload::V1(events))
+            }
+        };
+        result.map_err(|e| Error::new(std::io::ErrorKind::InvalidData, e))?;
+        Ok(writer.into_bytes_vec())
+    }
+
+    /// Decode a payload of this version.
+    pub fn decode(self, data: &[u8], limits: &DecodeLimits) -> Result<generated::RecordedEvents> {
+        Ok(self.decode_with_session(data, limits)?.events)
+    }
+
+    /// Decode a payload of this version together with the session of the client and the sequence number of the
+    /// payload.
+    pub fn decode_with_session(self, data: &[u8], limits: &DecodeLimits) -> Result<DecodedPayload> {
+        use super::Asn1Decode as _;
+        match self {
+            Self::V0 => Ok(DecodedPayload {
+                events: generated::RecordedEvents::decode_with_limits(data, limits)?,
+                session: None,
+                sequence: None,
+                sequence_nonce: None,
+            }),
+            Self::V1 => {
+                limits.check_input(data)?;
+                let mut reader = UperReader::from(Bits::from(data));
+                let payload = reader
+                    .read::<generated::MetricsPayload>()
+                    .map_err(|e| Error::new(std::io::ErrorKind::InvalidData, e))?;
+                #[allow(unreachable_patterns)]
+                let mut events = match payload {
+                    generated::MetricsPayload::V1(events) => events,
+                    _ => return Err(invalid("payload is not version 1")),
+                };
+                let session = events.session.take();
+                let sequence = events.sequence.take();
+                let sequence_nonce = events.sequence_nonce.take();
+                let events = generated::RecordedEvents::from(events);
+                limits.check_recorded_events(&events)?;
+                if let Some(session) = &session {
+                    limits.check_session(session)?;
+                }
+                if let Some(nonce) = &sequence_nonce {
+                    limits.check_string(nonce)?;
+                }
+                Ok(DecodedPayload {
+                    events,
+                    session: session.map(Into::into),
+                    sequence,
+                    sequence_nonce,
+                })
+            }
+        }
+    }
+}
+
+/// Version 0, the version clients send by default. Servers of 0.4.1 and older only understand version 0, clients opt
+/// into later versions once their server decodes them.
+impl Default for PayloadVersion {
+    fn default() -> Self {
+        Self::V0
+    }
+}
+
+/// A decoded payload, see [PayloadVersion::decode_with_session].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedPayload {
+    pub events: generated::RecordedEvents,
+    /// The session the client attached to the payload. Always `None` for [PayloadVersion::V0].
+    pub session: Option<ClientSession>,
+    /// The number of the payload within the session, see [crate::SequenceTracker]. Always `None` for
+    /// [PayloadVersion::V0].
+    pub sequence: Option<u64>,
+    /// See [PayloadMetadata::nonce]. `None` for [PayloadVersion::V0] and senders that predate the nonce.
+    pub sequence_nonce: Option<String>,
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+// Conversions between the version 0 types and the version 1 types. They have the same structure, version 1 only adds
+// extension markers.
+
+impl From<generated::RecordedEvents> for generated::RecordedEventsV1 {
+    fn from(value: generated::RecordedEvents) -> Self {
+        Self {
+            recording_started_at: value.recording_started_at,
+            events: value
+                .events
+                .into_iter()
+                .map(|event| generated::RecordedEventV1 {
+                    offset_ms: event.offset_ms,
+                    event: event.event.into(),
+                })
+                .collect(),
+            session: None,
+            sequence: None,
+            sequence_nonce: None,
+        }
+    }
+}
+
+impl From<generated::RecordedEventsV1> for generated::RecordedEvents {
+    fn from(value: generated::RecordedEventsV1) -> Self {
+        Self {
+            recording_started_at: value.recording_started_at,
+            events: value
+                .events
+                .into_iter()
+                .map(|event| generated::RecordedEvent {
+                    offset_ms: event.offset_ms,
+                    event: event.event.into(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<generated::Event> for generated::EventV1 {
+    fn from(value: generated::Event) -> Self {
+        match value {
+            generated::Event::Description(description) => Self::Description(generated::EventV1Description {
+                key_name: description.key_name,
+                metric_type: description.metric_type.into(),
+                unit: description.unit.map(Into::into),
+                description: description.description,
+            }),
+            generated::Event::Metric(metric) => Self::Metric(generated::EventV1Metric {
+                key: generated::EventV1MetricKey {
+                    name: metric.key.name,
+                    label: metric
+                        .key
+                        .label
+                        .into_iter()
+                        .map(|label| generated::EventV1MetricKeyLabel {
+                            key: label.key,
+                            value: label.value,
+                        })
+                        .collect(),
+                },
+                op: metric.op.into(),
+            }),
+        }
+    }
+}
+
+impl From<generated::EventV1> for generated::Event {
+    fn from(value: generated::EventV1) -> Self {
+        match value {
+            generated::EventV1::Description(description) => Self::Description(generated::EventDescription {
+                key_name: description.key_name,
+                metric_type: description.metric_type.into(),
+                unit: description.unit.map(Into::into),
+                description: description.description,
+            }),
+            generated::EventV1::Metric(metric) => Self::Metric(generated::EventMetric {
+                key: generated::EventMetricKey {
+                    name: metric.key.name,
+                    label: metric
+                        .key
+                        .label
+                        .into_iter()
+                        .map(|label| generated::EventMetricKeyLabel {
+                            key: label.key,
+                            value: label.value,
+                        })
+                        .collect(),
+                },
+                op: metric.op.into(),
+            }),
+        }
+    }
+}
+
+/// Implements the conversions in both directions between a version 0 enum and its version 1 copy.
+macro_rules! mirror_enum {
+    ($v0:ident, $v1:ident, [$($variant:ident $(($value:ident))?),* $(,)?]) => {
+        impl From<generated::$v0> for generated::$v1 {
+            fn from(value: generated::$v0) -> Self {
+                match value {
+                    $(generated::$v0::$variant $(($value))? => Self::$variant $(($value))?,)*
+                }
+            }
+        }
+
+        impl From<generated::$v1> for generated::$v0 {
+            fn from(value: generated::$v1) -> Self {
+                match value {
+                    $(generated::$v1::$variant $(($value))? => Self::$variant $(($value))?,)*
+                }
+            }
+        }
+    };
+}
+
+mirror_enum!(
+    MetricOperation,
+    MetricOperationV1,
+    [
+        IncrementCounter(val),
+        SetCounter(val),
+        IncrementGauge(val),
+        DecrementGauge(val),
+        SetGauge(val),
+        RecordHistogram(val),
+    ]
+);
+
+mirror_enum!(MetricType, MetricTypeV1, [Counter, Gauge, Histogram]);
+
+mirror_enum!(
+    Unit,
+    UnitV1,
+    [
+        Count,
+        Percent,
+        Seconds,
+        Milliseconds,
+        Microseconds,
+        Nanoseconds,
+        Tebibytes,
+        Gibibytes,
+        Mebibytes,
+        Kibibytes,
+        Bytes,
+        TerabitsPerSecond,
+        GigabitsPerSecond,
+        MegabitsPerSecond,
+        KilobitsPerSecond,
+        BitsPerSecond,
+        CountPerSecond,
+    ]
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Event,
+        MetricOperation,
+        MetricType,
+        RecordedEvent,
+    };
+    use chrono::prelude::*;
+    use metrics::Key;
+
+    const UNITS: [metrics::Unit; 17] = [
+        metrics::Unit::Count,
+        metrics::Unit::Percent,
+        metrics::Unit::Seconds,
+        metrics::Unit::Milliseconds,
+        metrics::Unit::Microseconds,
+        metrics::Unit::Nanoseconds,
+        metrics::Unit::Tebibytes,
+        metrics::Unit::Gibibytes,
+        metrics::Unit::Mebibytes,
+        metrics::Unit::Kibibytes,
+        metrics::Unit::Bytes,
+        metrics::Unit::TerabitsPerSecond,
+        metrics::Unit::GigabitsPerSecond,
+        metrics::Unit::MegabitsPerSecond,
+        metrics::Unit::KilobitsPerSecond,
+        metrics::Unit::BitsPerSecond,
+        metrics::Unit::CountPerSecond,
+    ];
+
+    /// Every alternative of the extensible `EventV1` and `MetricOperationV1` choices, every value of the extensible
+    /// `MetricTypeV1` and `UnitV1` enumerations and the extensible inline `SEQUENCE OF SEQUENCE` of labels.
+    fn events() -> generated::RecordedEvents {
+        let start = Utc.timestamp_opt(1_714_564_800, 0).unwrap();
+        let descriptions = UNITS.iter().enumerate().map(|(i, unit)| Event::Description {
+            name: format!("metric_{i}").into(),
+            metric_type: [MetricType::Counter, MetricType::Gauge, MetricType::Histogram][i % 3],
+            unit: Some(*unit),
+            description: "".into(),
+        });
+        let key = Key::from_parts("requests", &[("route", "/api"), ("status", "200")]);
+        let operations = [
+            MetricOperation::IncrementCounter(1),
+            MetricOperation::SetCounter(u64::MAX),
+            MetricOperation::IncrementGauge(0.5),
+            MetricOperation::DecrementGauge(-1.5),
+            MetricOperation::SetGauge(f64::MAX),
+            MetricOperation::RecordHistogram(12.25),
+        ]
+        .into_iter()
+        .map(|op| Event::Metric { key: key.clone(), op });
+        let events = descriptions
+            .chain(operations)
+            .map(|event| RecordedEvent {
+                timestamp: start,
+                event,
+            })
+            .collect();
+        generated::RecordedEvents::new(start, events)
+    }
+
+    fn session() -> ClientSession {
+        ClientSession::new()
+            .session_id("session")
+            .app_version("1.2.3")
+            .resource_attribute("page", "/player")
+    }
+
+    fn metadata() -> PayloadMetadata {
+        PayloadMetadata {
+            session_id: "session".to_string(),
+            nonce: "a1b2".to_string(),
+            sequence: 42,
+        }
+    }
+
+    #[test]
+    fn v1_roundtrip() {
+        let events = events();
+        let bytes = PayloadVersion::V1
+            .encode_with_sequence(&events, Some(&session()), Some(&metadata()))
+            .unwrap();
+        let decoded = PayloadVersion::V1
+            .decode_with_session(&bytes, &DecodeLimits::default())
+            .unwrap();
+        assert_eq!(
+            decoded,
+            DecodedPayload {
+                events,
+                session: Some(session()),
+                sequence: Some(42),
+                sequence_nonce: Some("a1b2".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn default_is_v0() {
+        let events = events();
+        assert_eq!(PayloadVersion::default(), PayloadVersion::V0);
+        assert_eq!(PayloadVersion::default().content_type(), PAYLOAD_CONTENT_TYPE_V0);
+        assert_eq!(PayloadVersion::default().websocket_protocol(), None);
+        // Version 0 drops the session and the sequence number, servers of 0.4.1 and older decode it.
+        let bytes = PayloadVersion::default()
+            .encode_with_sequence(&events, Some(&session()), Some(&metadata()))
+            .unwrap();
+        use crate::Asn1Encode as _;
+        assert_eq!(bytes, events.encode().unwrap());
+    }
+
+    /// `MetricsPayload` and `RecordedEventsV1` of a future schema with one more alternative and one more extension
+    /// addition.
+    mod future {
+        use crate::asn::generated::{
+            ClientSession,
+            RecordedEventV1,
+            Timestamp,
+        };
+        use asn1rs::prelude::*;
+
+        #[asn(choice, extensible_after(V1))]
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum MetricsPayload {
+            #[asn(complex(RecordedEvents, tag(UNIVERSAL(16))))]
+            V1(RecordedEvents),
+            #[asn(complex(RecordedEvents, tag(UNIVERSAL(16))))]
+            V2(RecordedEvents),
+        }
+
+        #[asn(sequence, extensible_after(sequence_nonce))]
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct RecordedEvents {
+            #[asn(complex(Timestamp, tag(UNIVERSAL(16))))]
+            pub recording_started_at: Timestamp,
+            #[asn(sequence_of(complex(RecordedEventV1, tag(UNIVERSAL(16)))))]
+            pub events: Vec<RecordedEventV1>,
+            #[asn(optional(complex(ClientSession, tag(UNIVERSAL(16)))))]
+            pub session: Option<ClientSession>,
+            #[asn(optional(integer(0..9223372036854775807)))]
+            pub sequence: Option<u64>,
+            #[asn(optional(utf8string))]
+            pub sequence_nonce: Option<String>,
+            #[asn(optional(utf8string))]
+            pub trace_id: Option<String>,
+        }
+    }
+
+    fn future_events() -> future::RecordedEvents {
+        let events = generated::RecordedEventsV1::from(events());
+        future::RecordedEvents {
+            recording_started_at: events.recording_started_at,
+            events: events.events,
+            session: Some(session().into()),
+            sequence: Some(42),
+            sequence_nonce: Some("a1b2".to_string()),
+            trace_id: Some("4bf92f3577b34da6a3ce929d0e0e4736".to_string()),
+        }
+    }
+
+    fn encode_future(payload: &future::MetricsPayload) -> Vec<u8> {
+        let mut writer = UperWriter::default();
+        writer.write(payload).unwrap();
+        writer.into_bytes_vec()
+    }
+
+    #[test]
+    fn skips_unknown_extension_additions() {
+        let bytes = encode_future(&future::MetricsPayload::V1(future_events()));
+        let decoded = PayloadVersion::V1
+            .decode_with_session(&bytes, &DecodeLimits::default())
+            .unwrap();
+        assert_eq!(
+            decoded,
+            DecodedPayload {
+                events: events(),
+                session: Some(session()),
+                sequence: Some(42),
+                sequence_nonce: Some("a1b2".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn newer_decoders_read_payloads_without_extension_additions() {
+        // Current payloads carry no extension additions, a decoder that knows one reads them. See the comment on
+        // `MetricsPayload` in metrics.asn for why payloads must never carry only some of the additions it knows.
+        let bytes = PayloadVersion::V1
+            .encode_with_sequence(&events(), Some(&session()), Some(&metadata()))
+            .unwrap();
+        let mut reader = UperReader::from(Bits::from(&bytes[..]));
+        let decoded = reader.read::<future::MetricsPayload>().unwrap();
+        assert_eq!(
+            decoded,
+            future::MetricsPayload::V1(future::RecordedEvents {
+                trace_id: None,
+                ..future_events()
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_alternatives() {
+        // Unlike extension additions of a sequence, a decoder can't make sense of an alternative or an enumeration
+        // value it doesn't know. New alternatives need servers that know them.
+        let bytes = encode_future(&future::MetricsPayload::V2(future_events()));
+        assert!(PayloadVersion::V1
+            .decode_with_session(&bytes, &DecodeLimits::default())
+            .is_err());
+    }
+}
@@ -1,7 +1,9 @@
 use super::{
     generated,
+    limits::invalid,
     Asn1Decode,
     Asn1Encode,
+    DecodeLimits,
     Error,
     Result,
 };
@@ -14,16 +16,22 @@ use asn1rs::prelude::*;
 use chrono::prelude::*;
 
 impl RecordedEvent {
-    fn from_asn_with_base_time(event: generated::RecordedEvent, base_time: DateTime<Utc>) -> Self {
+    fn try_from_asn_with_base_time(event: generated::RecordedEvent, base_time: DateTime<Utc>) -> Result<Self> {
         let generated::RecordedEvent { offset_ms, event } = event;
-        Self {
-            timestamp: base_time + chrono::Duration::milliseconds(offset_ms as i64),
-            event: event.into(),
-        }
+        let timestamp = base_time
+            .checked_add_signed(chrono::Duration::milliseconds(i64::from(offset_ms)))
+            .ok_or_else(|| invalid("event timestamp out of range"))?;
+        Ok(Self {
+            timestamp,
+            event: event.try_into()?,
+        })
     }
 
     fn into_asn_with_base_time(self, base_time: DateTime<Utc>) -> generated::RecordedEvent {
-        let offset_ms = (self.timestamp - base_time).num_milliseconds() as u32;
+        // Events are never recorded before the batch started, clamp anyway instead of wrapping around.
+        let offset_ms = (self.timestamp - base_time)
+            .num_milliseconds()
+            .clamp(0, u32::MAX as i64) as u32;
         generated::RecordedEvent {
             offset_ms,
             event: self.event.into(),
@@ -50,9 +58,8 @@ impl From<RecordedEvent> for Event {
 
 impl generated::RecordedEvents {
     pub fn new(batch_start_time: DateTime<Utc>, events: Vec<RecordedEvent>) -> Self {
-        let unix_epoch = Utc.timestamp_opt(0, 0).unwrap();
         let duration = batch_start_time
-            .signed_duration_since(unix_epoch)
+            .signed_duration_since(DateTime::UNIX_EPOCH)
             .to_std()
             .unwrap_or_default();
         let recording_started_at = generated::Timestamp {
@@ -68,6 +75,24 @@ impl generated::RecordedEvents {
                 .collect(),
         }
     }
+
+    /// The number of events in this batch.
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Splits the batch into two at the given index. Both parts keep the same `recording_started_at` so that the event
+    /// timestamps are preserved.
+    pub fn split_off(&mut self, at: usize) -> Self {
+        Self {
+            recording_started_at: self.recording_started_at.clone(),
+            events: self.events.split_off(at),
+        }
+    }
 }
 
 impl Asn1Encode for generated::RecordedEvents {
@@ -83,29 +108,34 @@ impl Asn1Encode for generated::RecordedEvents {
 
 impl Asn1Decode for generated::RecordedEvents {
     /// Deserialize from asn1.
-    fn decode(data: &[u8]) -> Result<Self> {
+    fn decode_with_limits(data: &[u8], limits: &DecodeLimits) -> Result<Self> {
+        limits.check_input(data)?;
         let mut reader = UperReader::from(Bits::from(data));
-        reader
+        let events = reader
             .read::<generated::RecordedEvents>()
-            .map_err(|e| Error::new(std::io::ErrorKind::InvalidData, e))
+            .map_err(|e| Error::new(std::io::ErrorKind::InvalidData, e))?;
+        limits.check_recorded_events(&events)?;
+        Ok(events)
     }
 }
 
-impl From<generated::RecordedEvents> for Vec<RecordedEvent> {
-    fn from(value: generated::RecordedEvents) -> Self {
+impl TryFrom<generated::RecordedEvents> for Vec<RecordedEvent> {
+    type Error = Error;
+
+    fn try_from(value: generated::RecordedEvents) -> Result<Self> {
         let generated::RecordedEvents {
             recording_started_at,
             events,
         } = value;
 
-        let duration = chrono::Duration::seconds(recording_started_at.seconds as i64)
-            + chrono::Duration::nanoseconds(recording_started_at.nanos as i64);
-        let unix_epoch = Utc.timestamp_opt(0, 0).unwrap();
-        let recording_started_at = unix_epoch + duration;
+        let recording_started_at = i64::try_from(recording_started_at.seconds)
+            .ok()
+            .and_then(|seconds| DateTime::from_timestamp(seconds, recording_started_at.nanos))
+            .ok_or_else(|| invalid("recording_started_at out of range"))?;
 
         events
             .into_iter()
-            .map(|event| RecordedEvent::from_asn_with_base_time(event, recording_started_at))
+            .map(|event| RecordedEvent::try_from_asn_with_base_time(event, recording_started_at))
             .collect()
     }
 }
@@ -0,0 +1,46 @@
+use super::generated;
+use crate::ClientSession;
+
+impl From<generated::ClientSession> for ClientSession {
+    fn from(value: generated::ClientSession) -> Self {
+        let generated::ClientSession {
+            session_id,
+            client_instance_id,
+            app_version,
+            user_agent,
+            resource_attributes,
+        } = value;
+        Self {
+            session_id,
+            client_instance_id,
+            app_version,
+            user_agent,
+            resource_attributes: resource_attributes
+                .into_iter()
+                .map(|attribute| (attribute.key, attribute.value))
+                .collect(),
+        }
+    }
+}
+
+impl From<ClientSession> for generated::ClientSession {
+    fn from(value: ClientSession) -> Self {
+        let ClientSession {
+            session_id,
+            client_instance_id,
+            app_version,
+            user_agent,
+            resource_attributes,
+        } = value;
+        Self {
+            session_id,
+            client_instance_id,
+            app_version,
+            user_agent,
+            resource_attributes: resource_attributes
+                .into_iter()
+                .map(|(key, value)| generated::ClientSessionResourceAttributes { key, value })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,289 @@
+//! A byte aligned serialization of [RecordedEvents] and [ClientSession] to hand a batch to another wasm instance of
+//! the same build, like the compression worker of the exporter. Writing it is mostly copying strings, which is much
+//! cheaper than UPER encoding. It is no wire format: there is no versioning and both sides must run the same build.
+
+use super::{
+    generated,
+    limits::invalid,
+    Error,
+    RecordedEvents,
+    Result,
+};
+use crate::{
+    ClientSession,
+    MetricType,
+};
+
+pub fn write_events(out: &mut Vec<u8>, events: &RecordedEvents) {
+    write_u64(out, events.recording_started_at.seconds);
+    write_u32(out, events.recording_started_at.nanos);
+    write_u32(out, events.events.len() as u32);
+    for event in &events.events {
+        write_u32(out, event.offset_ms);
+        match &event.event {
+            generated::Event::Description(description) => {
+                out.push(0);
+                write_str(out, &description.key_name);
+                out.push(match MetricType::from(description.metric_type) {
+                    MetricType::Counter => 0,
+                    MetricType::Gauge => 1,
+                    MetricType::Histogram => 2,
+                });
+                write_option(out, description.unit.map(|unit| metrics::Unit::from(unit).as_str()));
+                write_str(out, &description.description);
+            }
+            generated::Event::Metric(metric) => {
+                out.push(1);
+                write_str(out, &metric.key.name);
+                write_u32(out, metric.key.label.len() as u32);
+                for label in &metric.key.label {
+                    write_str(out, &label.key);
+                    write_str(out, &label.value);
+                }
+                let (kind, value) = match &metric.op {
+                    generated::MetricOperation::IncrementCounter(value) => (0, value.to_le_bytes().to_vec()),
+                    generated::MetricOperation::SetCounter(value) => (1, value.to_le_bytes().to_vec()),
+                    generated::MetricOperation::IncrementGauge(value) => (2, value.clone()),
+                    generated::MetricOperation::DecrementGauge(value) => (3, value.clone()),
+                    generated::MetricOperation::SetGauge(value) => (4, value.clone()),
+                    generated::MetricOperation::RecordHistogram(value) => (5, value.clone()),
+                };
+                out.push(kind);
+                write_bytes(out, &value);
+            }
+        }
+    }
+}
+
+pub fn read_events(input: &mut &[u8]) -> Result<RecordedEvents> {
+    let recording_started_at = generated::Timestamp {
+        seconds: read_u64(input)?,
+        nanos: read_u32(input)?,
+    };
+    let len = read_u32(input)? as usize;
+    // Every event takes at least 6 bytes, don't trust `len` for the allocation.
+    let mut events = Vec::with_capacity(len.min(input.len() / 6));
+    for _ in 0..len {
+        let offset_ms = read_u32(input)?;
+        let event = match read_u8(input)? {
+            0 => generated::Event::Description(generated::EventDescription {
+                key_name: read_string(input)?,
+                metric_type: match read_u8(input)? {
+                    0 => MetricType::Counter,
+                    1 => MetricType::Gauge,
+                    2 => MetricType::Histogram,
+                    _ => return Err(invalid("unknown metric type")),
+                }
+                .into(),
+                unit: read_option(input)?
+                    .map(|unit| {
+                        metrics::Unit::from_string(&unit)
+                            .map(Into::into)
+                            .ok_or_else(|| invalid("unknown unit"))
+                    })
+                    .transpose()?,
+                description: read_string(input)?,
+            }),
+            1 => {
+                let name = read_string(input)?;
+                let len = read_u32(input)? as usize;
+                let mut label = Vec::with_capacity(len.min(input.len() / 8));
+                for _ in 0..len {
+                    label.push(generated::EventMetricKeyLabel {
+                        key: read_string(input)?,
+                        value: read_string(input)?,
+                    });
+                }
+                let kind = read_u8(input)?;
+                let value = read_bytes(input)?.to_vec();
+                let counter = || -> Result<u64> {
+                    Ok(u64::from_le_bytes(
+                        value.as_slice().try_into().map_err(|_| invalid("malformed counter"))?,
+                    ))
+                };
+                let op = match kind {
+                    0 => generated::MetricOperation::IncrementCounter(counter()?),
+                    1 => generated::MetricOperation::SetCounter(counter()?),
+                    2 => generated::MetricOperation::IncrementGauge(value),
+                    3 => generated::MetricOperation::DecrementGauge(value),
+                    4 => generated::MetricOperation::SetGauge(value),
+                    5 => generated::MetricOperation::RecordHistogram(value),
+                    _ => return Err(invalid("unknown metric operation")),
+                };
+                generated::Event::Metric(generated::EventMetric {
+                    key: generated::EventMetricKey { name, label },
+                    op,
+                })
+            }
+            _ => return Err(invalid("unknown event")),
+        };
+        events.push(generated::RecordedEvent { offset_ms, event });
+    }
+    Ok(RecordedEvents {
+        recording_started_at,
+        events,
+    })
+}
+
+pub fn write_session(out: &mut Vec<u8>, session: &ClientSession) {
+    write_option(out, session.session_id.as_deref());
+    write_option(out, session.client_instance_id.as_deref());
+    write_option(out, session.app_version.as_deref());
+    write_option(out, session.user_agent.as_deref());
+    write_u32(out, session.resource_attributes.len() as u32);
+    for (key, value) in &session.resource_attributes {
+        write_str(out, key);
+        write_str(out, value);
+    }
+}
+
+pub fn read_session(input: &mut &[u8]) -> Result<ClientSession> {
+    let mut session = ClientSession {
+        session_id: read_option(input)?,
+        client_instance_id: read_option(input)?,
+        app_version: read_option(input)?,
+        user_agent: read_option(input)?,
+        resource_attributes: Vec::new(),
+    };
+    for _ in 0..read_u32(input)? {
+        session
+            .resource_attributes
+            .push((read_string(input)?, read_string(input)?));
+    }
+    Ok(session)
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+pub fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+pub fn write_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Length prefixed bytes.
+pub fn write_bytes(out: &mut Vec<u8>, value: &[u8]) {
+    write_u32(out, value.len() as u32);
+    out.extend_from_slice(value);
+}
+
+pub fn write_str(out: &mut Vec<u8>, value: &str) {
+    write_bytes(out, value.as_bytes());
+}
+
+pub fn write_option(out: &mut Vec<u8>, value: Option<&str>) {
+    match value {
+        Some(value) => {
+            out.push(1);
+            write_str(out, value);
+        }
+        None => out.push(0),
+    }
+}
+
+pub fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if input.len() < len {
+        return Err(invalid("truncated transfer data"));
+    }
+    let (head, tail) = input.split_at(len);
+    *input = tail;
+    Ok(head)
+}
+
+pub fn read_u8(input: &mut &[u8]) -> Result<u8> {
+    Ok(take(input, 1)?[0])
+}
+
+pub fn read_u32(input: &mut &[u8]) -> Result<u32> {
+    Ok(u32::from_le_bytes(take(input, 4)?.try_into().expect("4 bytes")))
+}
+
+pub fn read_u64(input: &mut &[u8]) -> Result<u64> {
+    Ok(u64::from_le_bytes(take(input, 8)?.try_into().expect("8 bytes")))
+}
+
+pub fn read_bytes<'a>(input: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = read_u32(input)? as usize;
+    take(input, len)
+}
+
+pub fn read_string(input: &mut &[u8]) -> Result<String> {
+    String::from_utf8(read_bytes(input)?.to_vec()).map_err(|e| Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+pub fn read_option(input: &mut &[u8]) -> Result<Option<String>> {
+    match read_u8(input)? {
+        0 => Ok(None),
+        _ => read_string(input).map(Some),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Event,
+        MetricOperation,
+        RecordedEvent,
+    };
+    use metrics::Key;
+
+    #[test]
+    fn events_and_session_roundtrip() {
+        let start = chrono::DateTime::from_timestamp(1_700_000_000, 123).unwrap();
+        let events: Vec<RecordedEvent> = [
+            Event::Description {
+                name: "requests".into(),
+                metric_type: MetricType::Counter,
+                unit: Some(metrics::Unit::Count),
+                description: "requests sent".into(),
+            },
+            Event::Description {
+                name: "latency".into(),
+                metric_type: MetricType::Histogram,
+                unit: None,
+                description: "".into(),
+            },
+            Event::Metric {
+                key: Key::from_parts("requests", &[("route", "/api"), ("status", "200")]),
+                op: MetricOperation::IncrementCounter(3),
+            },
+            Event::Metric {
+                key: Key::from_name("latency"),
+                op: MetricOperation::RecordHistogram(12.5),
+            },
+            Event::Metric {
+                key: Key::from_name("memory"),
+                op: MetricOperation::DecrementGauge(-1.0),
+            },
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, event)| RecordedEvent {
+            timestamp: start + chrono::Duration::milliseconds(i as i64 * 10),
+            event,
+        })
+        .collect();
+        let events = RecordedEvents::new(start, events);
+        let session = ClientSession::new()
+            .session_id("session")
+            .app_version("1.2.3")
+            .resource_attribute("page", "/player");
+
+        let mut out = Vec::new();
+        write_events(&mut out, &events);
+        let events_len = out.len();
+        write_session(&mut out, &session);
+
+        let mut input = out.as_slice();
+        assert_eq!(read_events(&mut input).unwrap(), events);
+        assert_eq!(read_session(&mut input).unwrap(), session);
+        assert!(input.is_empty());
+
+        for len in 0..events_len {
+            assert!(read_events(&mut &out[..len]).is_err());
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Decompresses and decodes payloads on the receiving server, according to their `content-encoding`.
+
+use crate::{
+    DecodeLimits,
+    DecodedPayload,
+    PayloadVersion,
+    RecordedEvents,
+    Result,
+};
+use std::{
+    borrow::Cow,
+    io,
+};
+
+/// The default limit for the decompressed size of a payload, see [PayloadDecoder::max_decompressed_size].
+pub const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 16 * 1024 * 1024;
+
+/// Decompresses `data` according to the value of its `content-encoding` header and decodes the [RecordedEvents] in the
+/// [PayloadVersion] of its `content-type` header. Decompressed payloads are limited to [DEFAULT_MAX_DECOMPRESSED_SIZE],
+/// use [PayloadDecoder] to change that.
+///
+/// Which encodings are supported depends on the `decompress-brotli`, `decompress-gzip` and `decompress-zstd`
+/// features.
+pub fn decode_payload(
+    content_type: Option<&str>,
+    content_encoding: Option<&str>,
+    data: &[u8],
+) -> Result<RecordedEvents> {
+    PayloadDecoder::new().decode(content_type, content_encoding, data)
+}
+
+/// Decompresses and decodes payloads, see [decode_payload].
+#[derive(Debug, Clone)]
+pub struct PayloadDecoder {
+    max_decompressed_size: usize,
+    limits: DecodeLimits,
+    #[cfg(feature = "decompress-zstd")]
+    zstd_dictionaries: std::sync::Arc<crate::ZstdDictionaries>,
+}
+
+impl Default for PayloadDecoder {
+    fn default() -> Self {
+        Self {
+            max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+            limits: DecodeLimits::default(),
+            #[cfg(feature = "decompress-zstd")]
+            zstd_dictionaries: Default::default(),
+        }
+    }
+}
+
+impl PayloadDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Payloads that decompress to more than `size` bytes are rejected, this protects against decompression bombs.
+    pub fn max_decompressed_size(mut self, size: usize) -> Self {
+        self.max_decompressed_size = size;
+        self
+    }
+
+    /// Limits for the decoded payload, see [DecodeLimits].
+    pub fn decode_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// The dictionaries zstd payloads may be compressed with, see [crate::ZstdDictionaries]. The dictionary is picked
+    /// by the id in the zstd frame header, which matches the [crate::ZSTD_DICTIONARY_ID_HEADER] header the client
+    /// sends. Payloads compressed with an unknown dictionary are rejected.
+    #[cfg(feature = "decompress-zstd")]
+    pub fn zstd_dictionaries(mut self, dictionaries: impl Into<std::sync::Arc<crate::ZstdDictionaries>>) -> Self {
+        self.zstd_dictionaries = dictionaries.into();
+        self
+    }
+
+    pub fn decode(
+        &self,
+        content_type: Option<&str>,
+        content_encoding: Option<&str>,
+        data: &[u8],
+    ) -> Result<RecordedEvents> {
+        Ok(self.decode_with_session(content_type, content_encoding, data)?.events)
+    }
+
+    /// Like [Self::decode], also returns the [crate::ClientSession] the client attached to the payload.
+    pub fn decode_with_session(
+        &self,
+        content_type: Option<&str>,
+        content_encoding: Option<&str>,
+        data: &[u8],
+    ) -> Result<DecodedPayload> {
+        let version = PayloadVersion::from_content_type(content_type)?;
+        version.decode_with_session(&self.decompress(content_encoding, data)?, &self.limits)
+    }
+
+    /// Decompresses `data` according to `content_encoding` without decoding it.
+    pub fn decompress<'a>(&self, content_encoding: Option<&str>, data: &'a [u8]) -> io::Result<Cow<'a, [u8]>> {
+        let content_encoding = content_encoding.map(str::trim).unwrap_or_default();
+        match content_encoding.to_ascii_lowercase().as_str() {
+            "" | "identity" if data.len() > self.max_decompressed_size => Err(too_large(self.max_decompressed_size)),
+            "" | "identity" => Ok(Cow::Borrowed(data)),
+            #[cfg(feature = "decompress-brotli")]
+            "br" => self
+                .read_limited(brotli_decompressor::Decompressor::new(data, 4096))
+                .map(Cow::Owned),
+            #[cfg(feature = "decompress-gzip")]
+            "gzip" | "x-gzip" => self.read_limited(flate2::read::GzDecoder::new(data)).map(Cow::Owned),
+            #[cfg(feature = "decompress-gzip")]
+            "deflate" => self.read_limited(flate2::read::ZlibDecoder::new(data)).map(Cow::Owned),
+            #[cfg(feature = "decompress-zstd")]
+            "zstd" => self
+                .read_limited(self.zstd_dictionaries.decoder(None, data)?)
+                .map(Cow::Owned),
+            encoding => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unsupported content-encoding {encoding:?}"),
+            )),
+        }
+    }
+
+    #[cfg(any(
+        feature = "decompress-brotli",
+        feature = "decompress-gzip",
+        feature = "decompress-zstd"
+    ))]
+    fn read_limited(&self, reader: impl io::Read) -> io::Result<Vec<u8>> {
+        use io::Read as _;
+        let mut decompressed = Vec::new();
+        reader
+            .take(self.max_decompressed_size as u64 + 1)
+            .read_to_end(&mut decompressed)?;
+        if decompressed.len() > self.max_decompressed_size {
+            return Err(too_large(self.max_decompressed_size));
+        }
+        Ok(decompressed)
+    }
+}
+
+fn too_large(max_size: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("decompressed payload exceeds {max_size} bytes"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_oversized_payloads() {
+        let decoder = PayloadDecoder::new().max_decompressed_size(4);
+        assert_eq!(&*decoder.decompress(None, b"1234").unwrap(), b"1234");
+        assert!(decoder.decompress(Some("identity"), b"12345").is_err());
+        assert!(decoder.decompress(Some("compress"), b"1234").is_err());
+    }
+
+    #[cfg(feature = "decompress-gzip")]
+    #[test]
+    fn limits_decompressed_size() {
+        use std::io::Write as _;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&[0; 1024 * 1024]).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let decoder = PayloadDecoder::new().max_decompressed_size(1024 * 1024);
+        assert_eq!(
+            decoder.decompress(Some("gzip"), &compressed).unwrap().len(),
+            1024 * 1024
+        );
+        let decoder = decoder.max_decompressed_size(1024);
+        assert!(decoder.decompress(Some("gzip"), &compressed).is_err());
+    }
+
+    #[cfg(feature = "decompress-zstd")]
+    #[test]
+    fn decompresses_zstd_with_dictionary() {
+        let dictionary = crate::zstd_dictionary::tests::trained_dictionary();
+        let payload = b"counter.requests{route=/api/1, status=200} increment 3".to_vec();
+        let compressed = zstd::bulk::Compressor::with_dictionary(3, &dictionary)
+            .unwrap()
+            .compress(&payload)
+            .unwrap();
+
+        let err = PayloadDecoder::new().decompress(Some("zstd"), &compressed).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        let mut dictionaries = crate::ZstdDictionaries::new();
+        dictionaries.add(dictionary).unwrap();
+        let decoder = PayloadDecoder::new().zstd_dictionaries(dictionaries);
+        assert_eq!(&*decoder.decompress(Some("zstd"), &compressed).unwrap(), payload);
+        assert!(decoder
+            .max_decompressed_size(8)
+            .decompress(Some("zstd"), &compressed)
+            .is_err());
+    }
+}
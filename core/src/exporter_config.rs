@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+/// Configuration a collector can send back in the response to a metrics payload to steer the exporter. The response
+/// must have the content type [crate::EXPORTER_CONFIG_CONTENT_TYPE] and carry the ASN.1 encoded config.
+///
+/// Absent optional fields keep the current setting of the exporter. [Self::sampling] and [Self::disabled_metrics]
+/// replace the current rules, send an empty list to clear them.
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExporterConfig {
+    /// How often batches are sent. Transmitted in milliseconds. The exporter clamps it to between 1 second and 10
+    /// minutes.
+    pub send_frequency: Option<Duration>,
+    /// Only keep a fraction of the metrics events whose name starts with a prefix. The longest matching prefix
+    /// decides. Exporters only sample low priority events, disable a metric to drop all of its events.
+    pub sampling: Vec<SamplingRule>,
+    /// Names of metrics that are not sent at all.
+    pub disabled_metrics: Vec<String>,
+    /// The compression of the payloads.
+    pub compression: Option<PayloadCompression>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SamplingRule {
+    pub prefix: String,
+    /// The fraction of events to keep, between 0 and 1. Transmitted with a precision of 1/1000.
+    pub rate: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PayloadCompression {
+    None,
+    Brotli,
+    Zstd,
+}
@@ -1,16 +1,33 @@
 //! asn.1 metrics implementation using the asn1rs crate.
 
 mod asn;
+mod decode;
 mod event;
+mod exporter_config;
+mod sequence;
+mod session;
 pub mod util_time;
+mod zstd_dictionary;
 
+#[doc(hidden)]
+pub use asn::transfer;
 pub use asn::{
     Asn1Decode,
     Asn1Encode,
+    DecodeLimits,
+    DecodedPayload,
     Error,
     Events,
+    PayloadVersion,
     RecordedEvents,
     Result,
+    PAYLOAD_CONTENT_TYPE_V0,
+    PAYLOAD_CONTENT_TYPE_V1,
+};
+pub use decode::{
+    decode_payload,
+    PayloadDecoder,
+    DEFAULT_MAX_DECOMPRESSED_SIZE,
 };
 pub use event::{
     Event,
@@ -18,3 +35,30 @@ pub use event::{
     MetricType,
     RecordedEvent,
 };
+pub use exporter_config::{
+    ExporterConfig,
+    PayloadCompression,
+    SamplingRule,
+};
+pub use sequence::{
+    PayloadMetadata,
+    SequenceCheck,
+    SequenceTracker,
+    IDEMPOTENCY_KEY_HEADER,
+};
+pub use session::ClientSession;
+#[cfg(feature = "decompress-zstd")]
+pub use zstd_dictionary::{
+    decompress_zstd_with_dictionary,
+    ZstdDictionaries,
+};
+pub use zstd_dictionary::{
+    zstd_dictionary_id,
+    ZSTD_DICTIONARY_ID_HEADER,
+};
+
+/// The text frame a server sends back over a WebSocket connection to acknowledge a received payload.
+pub const WEBSOCKET_ACK: &str = "ok";
+
+/// The content type of a response that carries an ASN.1 encoded [ExporterConfig].
+pub const EXPORTER_CONFIG_CONTENT_TYPE: &str = "application/vnd.metrics-exporter-wasm.config";
@@ -0,0 +1,259 @@
+//! Sequence numbers of payloads, to detect duplicated and lost payloads on the server.
+
+use crate::DecodedPayload;
+use std::{
+    collections::HashMap,
+    ops::Range,
+};
+
+/// The header that carries [PayloadMetadata::idempotency_key].
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Identifies a payload: the sender numbers its payloads per start, starting at 0. Retries of a payload keep its
+/// number. The number is part of the payload (see [DecodedPayload::sequence]) and HTTP transports can also send it in
+/// the [IDEMPOTENCY_KEY_HEADER] header, so servers can drop duplicates before decoding.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PayloadMetadata {
+    /// The [crate::ClientSession::session_id] of the sender.
+    pub session_id: String,
+    /// Random per start of the sender. The session id may be reused across starts, e.g. when it is kept in session
+    /// storage over page reloads, while the sequence restarts at 0.
+    pub nonce: String,
+    pub sequence: u64,
+}
+
+impl PayloadMetadata {
+    /// `<session id>:<nonce>:<sequence>`.
+    pub fn idempotency_key(&self) -> String {
+        format!("{}:{}", self.sequence_key(), self.sequence)
+    }
+
+    /// Parse an [Self::idempotency_key].
+    pub fn from_idempotency_key(key: &str) -> Option<Self> {
+        let (key, sequence) = key.rsplit_once(':')?;
+        let (session_id, nonce) = key.rsplit_once(':')?;
+        Some(Self {
+            session_id: session_id.to_string(),
+            nonce: nonce.to_string(),
+            sequence: sequence.parse().ok()?,
+        })
+    }
+
+    /// `<session id>:<nonce>`, the numbers of the payloads with the same key form one sequence.
+    pub fn sequence_key(&self) -> String {
+        format!("{}:{}", self.session_id, self.nonce)
+    }
+
+    /// The metadata of a decoded payload, if it has a session id and a sequence number. Payloads of senders that
+    /// predate the nonce get an empty one.
+    pub fn from_payload(payload: &DecodedPayload) -> Option<Self> {
+        Some(Self {
+            session_id: payload.session.as_ref()?.session_id.clone()?,
+            nonce: payload.sequence_nonce.clone().unwrap_or_default(),
+            sequence: payload.sequence?,
+        })
+    }
+}
+
+/// The result of [SequenceTracker::check].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SequenceCheck {
+    /// The next payload of the session.
+    InOrder,
+    /// The payload was seen before, e.g. because a retry was sent after the server already processed the payload.
+    /// Drop it.
+    Duplicate,
+    /// Payloads were skipped, they were lost or arrive later. The first payload of a session reports a gap if its
+    /// number is not 0.
+    Gap { missing: Range<u64> },
+    /// A payload that was reported missing by a [SequenceCheck::Gap] arrived after all.
+    Late,
+}
+
+/// Tracks the [PayloadMetadata::sequence] per [PayloadMetadata::sequence_key] on the server to report duplicated and
+/// lost payloads. Every start of a sender gets its own key, so a session id that is reused after a restart doesn't
+/// report the new payloads as duplicates.
+///
+/// The state is kept in memory, after a restart every session that keeps sending reports a gap once. The least
+/// recently seen sessions are forgotten when there are more than [Self::max_sessions].
+#[derive(Debug)]
+pub struct SequenceTracker {
+    sessions: HashMap<String, SessionSequence>,
+    max_sessions: usize,
+    max_missing_ranges: usize,
+    tick: u64,
+}
+
+#[derive(Debug)]
+struct SessionSequence {
+    next: u64,
+    missing: Vec<Range<u64>>,
+    last_seen: u64,
+}
+
+impl Default for SequenceTracker {
+    fn default() -> Self {
+        Self {
+            sessions: HashMap::new(),
+            max_sessions: 10_000,
+            max_missing_ranges: 64,
+            tick: 0,
+        }
+    }
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many sessions to track at most, 10 000 by default.
+    pub fn max_sessions(mut self, max_sessions: usize) -> Self {
+        self.max_sessions = max_sessions.max(1);
+        self
+    }
+
+    /// How many ranges of missing payloads to remember per session, 64 by default. Payloads of older gaps that arrive
+    /// late are reported as [SequenceCheck::Duplicate].
+    pub fn max_missing_ranges(mut self, max_missing_ranges: usize) -> Self {
+        self.max_missing_ranges = max_missing_ranges;
+        self
+    }
+
+    /// Check the payload `sequence` of the [PayloadMetadata::sequence_key] `key` and remember it.
+    pub fn check(&mut self, key: &str, sequence: u64) -> SequenceCheck {
+        self.tick += 1;
+        let tick = self.tick;
+        if !self.sessions.contains_key(key) && self.sessions.len() >= self.max_sessions {
+            self.evict();
+        }
+        let session = self.sessions.entry(key.to_string()).or_insert_with(|| SessionSequence {
+            next: 0,
+            missing: Vec::new(),
+            last_seen: tick,
+        });
+        session.last_seen = tick;
+
+        if sequence >= session.next {
+            let missing = session.next..sequence;
+            session.next = sequence.saturating_add(1);
+            if missing.is_empty() {
+                return SequenceCheck::InOrder;
+            }
+            session.missing.push(missing.clone());
+            if session.missing.len() > self.max_missing_ranges {
+                session.missing.remove(0);
+            }
+            return SequenceCheck::Gap { missing };
+        }
+
+        let Some(index) = session.missing.iter().position(|range| range.contains(&sequence)) else {
+            return SequenceCheck::Duplicate;
+        };
+        let range = session.missing.remove(index);
+        let after = sequence + 1..range.end;
+        if !after.is_empty() {
+            session.missing.insert(index, after);
+        }
+        let before = range.start..sequence;
+        if !before.is_empty() {
+            session.missing.insert(index, before);
+        }
+        SequenceCheck::Late
+    }
+
+    /// Like [Self::check], for payloads that carry [PayloadMetadata]. Returns `None` otherwise.
+    pub fn check_payload(&mut self, payload: &DecodedPayload) -> Option<SequenceCheck> {
+        let metadata = PayloadMetadata::from_payload(payload)?;
+        Some(self.check(&metadata.sequence_key(), metadata.sequence))
+    }
+
+    /// Stop tracking a [PayloadMetadata::sequence_key], e.g. when the client said goodbye.
+    pub fn forget(&mut self, key: &str) {
+        self.sessions.remove(key);
+    }
+
+    fn evict(&mut self) {
+        if let Some(session_id) = self
+            .sessions
+            .iter()
+            .min_by_key(|(_, session)| session.last_seen)
+            .map(|(session_id, _)| session_id.clone())
+        {
+            self.sessions.remove(&session_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_duplicates_and_gaps() {
+        let mut tracker = SequenceTracker::new();
+        assert_eq!(tracker.check("a", 0), SequenceCheck::InOrder);
+        assert_eq!(tracker.check("a", 1), SequenceCheck::InOrder);
+        assert_eq!(tracker.check("a", 1), SequenceCheck::Duplicate);
+        assert_eq!(tracker.check("a", 5), SequenceCheck::Gap { missing: 2..5 });
+        assert_eq!(tracker.check("a", 3), SequenceCheck::Late);
+        assert_eq!(tracker.check("a", 3), SequenceCheck::Duplicate);
+        assert_eq!(tracker.check("a", 2), SequenceCheck::Late);
+        assert_eq!(tracker.check("a", 4), SequenceCheck::Late);
+        assert_eq!(tracker.check("a", 6), SequenceCheck::InOrder);
+        assert_eq!(tracker.check("b", 3), SequenceCheck::Gap { missing: 0..3 });
+    }
+
+    #[test]
+    fn evicts_least_recently_seen_sessions() {
+        let mut tracker = SequenceTracker::new().max_sessions(2);
+        tracker.check("a", 0);
+        tracker.check("b", 0);
+        tracker.check("a", 1);
+        tracker.check("c", 0);
+        assert_eq!(tracker.check("a", 1), SequenceCheck::Duplicate);
+        assert_eq!(tracker.check("b", 1), SequenceCheck::Gap { missing: 0..1 });
+    }
+
+    #[test]
+    fn restarted_sender_is_a_new_sequence() {
+        let payload = |nonce: &str, sequence| DecodedPayload {
+            events: crate::RecordedEvents::from(Vec::new()),
+            session: Some(crate::ClientSession::new().session_id("kept-across-reloads")),
+            sequence: Some(sequence),
+            sequence_nonce: Some(nonce.to_string()),
+        };
+        let mut tracker = SequenceTracker::new();
+        assert_eq!(
+            tracker.check_payload(&payload("first", 0)),
+            Some(SequenceCheck::InOrder)
+        );
+        assert_eq!(
+            tracker.check_payload(&payload("first", 1)),
+            Some(SequenceCheck::InOrder)
+        );
+        assert_eq!(
+            tracker.check_payload(&payload("second", 0)),
+            Some(SequenceCheck::InOrder)
+        );
+        assert_eq!(
+            tracker.check_payload(&payload("first", 1)),
+            Some(SequenceCheck::Duplicate)
+        );
+    }
+
+    #[test]
+    fn idempotency_key() {
+        let metadata = PayloadMetadata {
+            session_id: "4f1c:2a9e".to_string(),
+            nonce: "b7d3".to_string(),
+            sequence: 42,
+        };
+        assert_eq!(metadata.idempotency_key(), "4f1c:2a9e:b7d3:42");
+        assert_eq!(
+            PayloadMetadata::from_idempotency_key(&metadata.idempotency_key()),
+            Some(metadata)
+        );
+        assert_eq!(PayloadMetadata::from_idempotency_key("no-nonce:42"), None);
+    }
+}
@@ -0,0 +1,48 @@
+/// Describes the client that sent a payload, see `MetricsHttpSender::session` of the exporter. Set once on the
+/// exporter and attached to every payload of [crate::PayloadVersion::V1] and later, servers get it from
+/// [crate::DecodedPayload::session].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "utoipa-schema", derive(utoipa::ToSchema))]
+pub struct ClientSession {
+    /// Identifies a browser session, e.g. a page load.
+    pub session_id: Option<String>,
+    /// Identifies the browser across sessions, e.g. a random id kept in local storage.
+    pub client_instance_id: Option<String>,
+    pub app_version: Option<String>,
+    /// A short summary like `Firefox 131 / Linux`, not the full user agent string.
+    pub user_agent: Option<String>,
+    /// Custom attributes of the client, like the page or the deployment environment.
+    pub resource_attributes: Vec<(String, String)>,
+}
+
+impl ClientSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn session_id(mut self, session_id: impl ToString) -> Self {
+        self.session_id = Some(session_id.to_string());
+        self
+    }
+
+    pub fn client_instance_id(mut self, client_instance_id: impl ToString) -> Self {
+        self.client_instance_id = Some(client_instance_id.to_string());
+        self
+    }
+
+    pub fn app_version(mut self, app_version: impl ToString) -> Self {
+        self.app_version = Some(app_version.to_string());
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl ToString) -> Self {
+        self.user_agent = Some(user_agent.to_string());
+        self
+    }
+
+    pub fn resource_attribute(mut self, key: impl ToString, value: impl ToString) -> Self {
+        self.resource_attributes.push((key.to_string(), value.to_string()));
+        self
+    }
+}
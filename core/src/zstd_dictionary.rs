@@ -0,0 +1,160 @@
+//! Support for zstd dictionaries. Metric payloads are small and repetitive, a dictionary trained on captured payloads
+//! improves the compression ratio considerably.
+
+/// The header that carries the id of the dictionary a zstd compressed payload was compressed with.
+pub const ZSTD_DICTIONARY_ID_HEADER: &str = "x-zstd-dictionary-id";
+
+const ZSTD_DICTIONARY_MAGIC: u32 = 0xEC30A437;
+
+/// Returns the id of a dictionary in the zstd dictionary format (e.g. as produced by `zstd --train`). Returns `None`
+/// for raw content dictionaries and for dictionaries with id 0, which carry no id.
+pub fn zstd_dictionary_id(dictionary: &[u8]) -> Option<u32> {
+    let magic = u32::from_le_bytes(dictionary.get(0..4)?.try_into().ok()?);
+    if magic != ZSTD_DICTIONARY_MAGIC {
+        return None;
+    }
+    let id = u32::from_le_bytes(dictionary.get(4..8)?.try_into().ok()?);
+    (id != 0).then_some(id)
+}
+
+/// The dictionaries a server accepts, by id. Use [ZstdDictionaries::decompress] with the value of the
+/// [ZSTD_DICTIONARY_ID_HEADER] header.
+#[cfg(feature = "decompress-zstd")]
+#[derive(Debug, Default, Clone)]
+pub struct ZstdDictionaries {
+    dictionaries: std::collections::HashMap<u32, Vec<u8>>,
+}
+
+#[cfg(feature = "decompress-zstd")]
+impl ZstdDictionaries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `dictionary` and returns its id. Fails if the dictionary has no id.
+    pub fn add(&mut self, dictionary: impl Into<Vec<u8>>) -> std::io::Result<u32> {
+        let dictionary = dictionary.into();
+        let id = zstd_dictionary_id(&dictionary)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a zstd dictionary with an id"))?;
+        self.dictionaries.insert(id, dictionary);
+        Ok(id)
+    }
+
+    pub fn get(&self, id: u32) -> Option<&[u8]> {
+        self.dictionaries.get(&id).map(Vec::as_slice)
+    }
+
+    /// Decompresses `data` with the dictionary `id`. `id` is the value of the [ZSTD_DICTIONARY_ID_HEADER] header, if
+    /// any. Without an id, the dictionary named in the zstd frame header is used, frames without one are decompressed
+    /// without a dictionary.
+    pub fn decompress(&self, id: Option<&str>, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        use std::io::Read as _;
+        let mut decompressed = Vec::new();
+        self.decoder(id, data)?.read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    }
+
+    /// A streaming decoder for `data`, see [Self::decompress].
+    pub(crate) fn decoder<'a>(
+        &self,
+        id: Option<&str>,
+        data: &'a [u8],
+    ) -> std::io::Result<zstd::stream::read::Decoder<'static, &'a [u8]>> {
+        let id = match id {
+            Some(id) => Some(id.trim().parse().map_err(|_| unknown_dictionary(id))?),
+            None => zstd::zstd_safe::get_dict_id_from_frame(data).map(u32::from),
+        };
+        let dictionary = match id {
+            Some(id) => self.get(id).ok_or_else(|| unknown_dictionary(id))?,
+            None => &[],
+        };
+        zstd::stream::read::Decoder::with_dictionary(data, dictionary)
+    }
+}
+
+#[cfg(feature = "decompress-zstd")]
+fn unknown_dictionary(id: impl std::fmt::Display) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::NotFound, format!("unknown zstd dictionary {id}"))
+}
+
+/// Decompresses a zstd payload that was compressed with `dictionary`.
+#[cfg(feature = "decompress-zstd")]
+pub fn decompress_zstd_with_dictionary(data: &[u8], dictionary: &[u8]) -> std::io::Result<Vec<u8>> {
+    use std::io::Read as _;
+    let mut decoder = zstd::stream::read::Decoder::with_dictionary(data, dictionary)?;
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn dictionary_with_id(id: u32) -> Vec<u8> {
+        let mut dictionary = ZSTD_DICTIONARY_MAGIC.to_le_bytes().to_vec();
+        dictionary.extend_from_slice(&id.to_le_bytes());
+        dictionary
+    }
+
+    #[test]
+    fn dictionary_ids() {
+        assert_eq!(zstd_dictionary_id(&dictionary_with_id(42)), Some(42));
+        // Id 0 means no id, raw content dictionaries have no header at all.
+        assert_eq!(zstd_dictionary_id(&dictionary_with_id(0)), None);
+        assert_eq!(zstd_dictionary_id(b"counter.requests gauge.memory"), None);
+        assert_eq!(zstd_dictionary_id(&ZSTD_DICTIONARY_MAGIC.to_le_bytes()), None);
+    }
+
+    #[cfg(feature = "decompress-zstd")]
+    pub(crate) fn trained_dictionary() -> Vec<u8> {
+        let samples: Vec<Vec<u8>> = (0..1000)
+            .map(|i| {
+                format!(
+                    "counter.requests{{route=/api/{}, status={}}} increment {}",
+                    i % 17,
+                    200 + i % 5,
+                    i
+                )
+                .into_bytes()
+            })
+            .collect();
+        zstd::dict::from_samples(&samples, 4096).unwrap()
+    }
+
+    #[cfg(feature = "decompress-zstd")]
+    #[test]
+    fn decompress_with_dictionaries() {
+        let dictionary = trained_dictionary();
+        let mut dictionaries = ZstdDictionaries::new();
+        let id = dictionaries.add(dictionary.clone()).unwrap();
+        assert_eq!(dictionaries.get(id), Some(dictionary.as_slice()));
+        assert!(dictionaries.add(b"no header".to_vec()).is_err());
+
+        let payload = b"counter.requests{route=/api/3, status=201} increment 7".to_vec();
+        let compressed = zstd::bulk::Compressor::with_dictionary(3, &dictionary)
+            .unwrap()
+            .compress(&payload)
+            .unwrap();
+
+        assert_eq!(
+            dictionaries.decompress(Some(&id.to_string()), &compressed).unwrap(),
+            payload
+        );
+        // Without the header, the id in the frame header picks the dictionary.
+        assert_eq!(dictionaries.decompress(None, &compressed).unwrap(), payload);
+        assert_eq!(
+            dictionaries
+                .decompress(None, &zstd::encode_all(&payload[..], 3).unwrap())
+                .unwrap(),
+            payload
+        );
+
+        for unknown in [id.wrapping_add(1).to_string(), "not-a-number".to_string()] {
+            let err = dictionaries.decompress(Some(&unknown), &compressed).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        }
+        let err = ZstdDictionaries::new().decompress(None, &compressed).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+}
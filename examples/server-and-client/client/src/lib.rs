@@ -1,7 +1,10 @@
 use metrics_exporter_wasm::{
+    Asn1Encoder,
+    ClientSession,
     Compression,
     HttpPostTransport,
     MetricsHttpSender,
+    PayloadVersion,
     WasmRecorder,
 };
 use std::time::Duration;
@@ -10,7 +13,6 @@ use wasm_bindgen::prelude::*;
 #[wasm_bindgen]
 pub async fn setup() {
     console_error_panic_hook::set_once();
-    metrics_exporter_wasm::zstd_external::initialize().await;
 
     // Register tracing subscriber.
     {
@@ -35,6 +37,13 @@ pub async fn setup() {
             .endpoint(ENDPOINT),
     )
     .send_frequency(Duration::from_secs(1))
+    // The example server decodes every version, version 1 carries the session.
+    .encoder(Asn1Encoder::new().version(PayloadVersion::V1))
+    .session(
+        ClientSession::new()
+            .app_version(env!("CARGO_PKG_VERSION"))
+            .resource_attribute("example", "server-and-client"),
+    )
     .start_with_metrics_recorder(&recorder);
 
     // Run forever
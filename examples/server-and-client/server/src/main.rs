@@ -2,6 +2,10 @@
 extern crate tracing;
 
 use axum::{
+    extract::ws::{
+        Message,
+        WebSocketUpgrade,
+    },
     response::IntoResponse,
     routing::{
         get,
@@ -10,21 +14,27 @@ use axum::{
     Router,
 };
 use metrics_exporter_wasm_core::{
-    Asn1Decode as _,
+    DecodeLimits,
+    DecodedPayload,
     Event,
     MetricOperation,
     MetricType,
+    PayloadDecoder,
+    PayloadVersion,
     RecordedEvent,
-    RecordedEvents,
+    SequenceCheck,
+    SequenceTracker,
+    WEBSOCKET_ACK,
 };
 use std::{
     net::SocketAddr,
+    sync::{
+        LazyLock,
+        Mutex,
+    },
     time::Duration,
 };
-use tower_http::{
-    decompression::RequestDecompressionLayer,
-    services::ServeDir,
-};
+use tower_http::services::ServeDir;
 
 fn init_logging() {
     use tracing_subscriber::prelude::*;
@@ -51,70 +61,21 @@ async fn metrics() -> impl IntoResponse {
 }
 
 async fn receive_metrics(headers: axum::http::HeaderMap, data: axum::body::Bytes) -> hyper::StatusCode {
-    for (name, value) in headers {
+    for (name, value) in &headers {
         debug!("header: {:?}={:?}", name, value);
     }
 
-    match RecordedEvents::decode(&data) {
-        Ok(events) => {
-            let events: Vec<RecordedEvent> = events.into();
-            info!(n = %events.len(), "received metrics");
-            for RecordedEvent { timestamp, event } in events {
-                debug!(timestamp = %timestamp, "event");
-                match event {
-                    Event::Description {
-                        name,
-                        metric_type,
-                        unit,
-                        description,
-                    } => match metric_type {
-                        MetricType::Counter => {
-                            metrics::with_recorder(|recorder| recorder.describe_counter(name, unit, description));
-                        }
-                        MetricType::Gauge => {
-                            metrics::with_recorder(|recorder| recorder.describe_gauge(name, unit, description));
-                        }
-                        MetricType::Histogram => {
-                            metrics::with_recorder(|recorder| recorder.describe_histogram(name, unit, description));
-                        }
-                    },
-                    Event::Metric { key, op } => {
-                        let metadata = {
-                            static METADATA: metrics::Metadata<'static> =
-                                metrics::Metadata::new("", metrics::Level::INFO, None);
-                            &METADATA
-                        };
-
-                        match op {
-                            MetricOperation::IncrementCounter(value) => {
-                                metrics::with_recorder(|recorder| recorder.register_counter(&key, metadata))
-                                    .increment(value);
-                            }
-                            MetricOperation::SetCounter(value) => {
-                                metrics::with_recorder(|recorder| recorder.register_counter(&key, metadata))
-                                    .absolute(value);
-                            }
-                            MetricOperation::IncrementGauge(value) => {
-                                metrics::with_recorder(|recorder| recorder.register_gauge(&key, metadata))
-                                    .increment(value);
-                            }
-                            MetricOperation::DecrementGauge(value) => {
-                                metrics::with_recorder(|recorder| recorder.register_gauge(&key, metadata))
-                                    .decrement(value);
-                            }
-                            MetricOperation::SetGauge(value) => {
-                                metrics::with_recorder(|recorder| recorder.register_gauge(&key, metadata)).set(value);
-                            }
-                            MetricOperation::RecordHistogram(value) => {
-                                metrics::with_recorder(|recorder| recorder.register_histogram(&key, metadata))
-                                    .record(value);
-                            }
-                        }
-                    }
-                }
-            }
-            hyper::StatusCode::OK
-        }
+    let content_type = headers
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    let content_encoding = headers
+        .get(axum::http::header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok());
+    match PayloadDecoder::new()
+        .decode_with_session(content_type, content_encoding, &data)
+        .and_then(record_metrics)
+    {
+        Ok(()) => hyper::StatusCode::OK,
         Err(e) => {
             error!("failed to decode metrics: {:?}", e);
             hyper::StatusCode::BAD_REQUEST
@@ -122,6 +83,109 @@ async fn receive_metrics(headers: axum::http::HeaderMap, data: axum::body::Bytes
     }
 }
 
+/// Receives metrics over a WebSocket connection. Every binary frame is a payload that gets acknowledged with
+/// [WEBSOCKET_ACK]. The payload version is negotiated with the WebSocket subprotocol, clients without a subprotocol
+/// send version 0.
+async fn receive_metrics_ws(ws: WebSocketUpgrade) -> impl IntoResponse {
+    let protocols = PayloadVersion::V1.websocket_protocol();
+    ws.protocols(protocols).on_upgrade(|mut socket| async move {
+        let protocol = socket.protocol().and_then(|protocol| protocol.to_str().ok());
+        let version = match PayloadVersion::from_websocket_protocol(protocol) {
+            Ok(version) => version,
+            Err(e) => {
+                error!("unsupported metrics websocket: {:?}", e);
+                return;
+            }
+        };
+        while let Some(Ok(msg)) = socket.recv().await {
+            let reply = match msg {
+                Message::Binary(data) => match decode(version, &data).and_then(record_metrics) {
+                    Ok(()) => WEBSOCKET_ACK.to_string(),
+                    Err(e) => {
+                        error!("failed to decode metrics: {:?}", e);
+                        format!("failed to decode metrics: {e}")
+                    }
+                },
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            if socket.send(Message::Text(reply.into())).await.is_err() {
+                break;
+            }
+        }
+        debug!("metrics websocket closed");
+    })
+}
+
+fn decode(version: PayloadVersion, data: &[u8]) -> std::io::Result<DecodedPayload> {
+    version.decode_with_session(data, &DecodeLimits::default())
+}
+
+fn record_metrics(payload: DecodedPayload) -> std::io::Result<()> {
+    static SEQUENCES: LazyLock<Mutex<SequenceTracker>> = LazyLock::new(Default::default);
+    match SEQUENCES.lock().unwrap().check_payload(&payload) {
+        Some(SequenceCheck::Duplicate) => {
+            info!(sequence = ?payload.sequence, "dropping duplicate metrics payload");
+            return Ok(());
+        }
+        Some(SequenceCheck::Gap { missing }) => warn!(?missing, "metrics payloads lost or delayed"),
+        _ => {}
+    }
+
+    let events: Vec<RecordedEvent> = payload.events.try_into()?;
+    info!(n = %events.len(), session = ?payload.session, "received metrics");
+    for RecordedEvent { timestamp, event } in events {
+        debug!(timestamp = %timestamp, "event");
+        match event {
+            Event::Description {
+                name,
+                metric_type,
+                unit,
+                description,
+            } => match metric_type {
+                MetricType::Counter => {
+                    metrics::with_recorder(|recorder| recorder.describe_counter(name, unit, description));
+                }
+                MetricType::Gauge => {
+                    metrics::with_recorder(|recorder| recorder.describe_gauge(name, unit, description));
+                }
+                MetricType::Histogram => {
+                    metrics::with_recorder(|recorder| recorder.describe_histogram(name, unit, description));
+                }
+            },
+            Event::Metric { key, op } => {
+                let metadata = {
+                    static METADATA: metrics::Metadata<'static> =
+                        metrics::Metadata::new("", metrics::Level::INFO, None);
+                    &METADATA
+                };
+
+                match op {
+                    MetricOperation::IncrementCounter(value) => {
+                        metrics::with_recorder(|recorder| recorder.register_counter(&key, metadata)).increment(value);
+                    }
+                    MetricOperation::SetCounter(value) => {
+                        metrics::with_recorder(|recorder| recorder.register_counter(&key, metadata)).absolute(value);
+                    }
+                    MetricOperation::IncrementGauge(value) => {
+                        metrics::with_recorder(|recorder| recorder.register_gauge(&key, metadata)).increment(value);
+                    }
+                    MetricOperation::DecrementGauge(value) => {
+                        metrics::with_recorder(|recorder| recorder.register_gauge(&key, metadata)).decrement(value);
+                    }
+                    MetricOperation::SetGauge(value) => {
+                        metrics::with_recorder(|recorder| recorder.register_gauge(&key, metadata)).set(value);
+                    }
+                    MetricOperation::RecordHistogram(value) => {
+                        metrics::with_recorder(|recorder| recorder.register_histogram(&key, metadata)).record(value);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 fn metrics_test() {
     // By default `prometheus::default_registry()` is used.
     let recorder = metrics_prometheus::install();
@@ -153,8 +217,6 @@ async fn main() {
 
     // -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
 
-    let decompression_layer = RequestDecompressionLayer::new().br(true).zstd(true);
-
     let app = Router::<()>::new()
         .route("/fast", get(|| async {}))
         .route(
@@ -165,7 +227,7 @@ async fn main() {
         )
         .route("/metrics", get(metrics))
         .route("/receive-metrics", post(receive_metrics))
-        .layer(decompression_layer)
+        .route("/receive-metrics-ws", get(receive_metrics_ws))
         .fallback_service(ServeDir::new(public_dir).append_index_html_on_directories(true));
 
     let addr = SocketAddr::from(([127, 0, 0, 1], 8080));
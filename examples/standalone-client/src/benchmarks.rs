@@ -43,8 +43,12 @@ fn encode_and_compress_br<T>(events: T) -> Bytes
 where
     T: Asn1Encode + 'static + Sized,
 {
-    Compression::compress_br(&Bytes::from(events.encode().expect("failed to serialize events")))
-        .expect("failed to compress")
+    Compression::compress_br(
+        &Bytes::from(events.encode().expect("failed to serialize events")),
+        11,
+        22,
+    )
+    .expect("failed to compress")
 }
 
 fn asn_serialization_brotli() {
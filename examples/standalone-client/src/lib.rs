@@ -1,8 +1,13 @@
 mod benchmarks;
 mod util;
 
-use metrics_exporter_wasm::WasmRecorder;
-use tokio::sync::broadcast::error::RecvError;
+use metrics_exporter_wasm::{
+    Compression,
+    ConsoleTransport,
+    MetricsHttpSender,
+    WasmRecorder,
+};
+use std::time::Duration;
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
@@ -32,27 +37,17 @@ pub fn setup_metrics_test() {
         .build()
         .expect("failed to install recorder");
 
-    let mut rx = recorder.subscribe();
+    // Log what would be sent to the browser console.
+    let guard = MetricsHttpSender::new(
+        ConsoleTransport::new().compression(Some(Compression::Brotli { quality: 5, window: 22 })),
+    )
+    .send_frequency(Duration::from_secs(1))
+    .start_with_metrics_recorder(&recorder);
 
-    metrics::set_global_recorder(recorder).expect("failed to set global recorder");
+    // Run forever
+    guard.disarm();
 
-    wasm_bindgen_futures::spawn_local(async move {
-        tracing::debug!("metrics stream started");
-        loop {
-            match rx.recv().await {
-                Ok(event) => {
-                    tracing::info!(?event, "metrics");
-                }
-                Err(RecvError::Closed) => {
-                    break;
-                }
-                Err(RecvError::Lagged(lag)) => {
-                    tracing::warn!(?lag, "metrics lagging");
-                }
-            }
-        }
-        tracing::debug!("metrics stream closed");
-    });
+    metrics::set_global_recorder(recorder).expect("failed to set global recorder");
 
     tracing::debug!("metrics setup complete");
 }
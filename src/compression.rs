@@ -1,21 +1,141 @@
 #[derive(Debug, Clone, Copy)]
 pub enum Compression {
-    #[cfg(feature = "compress-zstd-external")]
-    /// Compress using zstd. This requires [`crate::zstd_external::initialize`] to be called first!
+    #[cfg(any(feature = "compress-zstd", feature = "compress-zstd-external"))]
+    /// Compress using zstd. With the `compress-zstd` feature this compresses in Rust, otherwise it requires
+    /// [`crate::zstd_external::initialize`] to be called first! When both features are enabled, `compress-zstd` is
+    /// used.
     Zstd { level: u8 },
 
+    #[cfg(feature = "compress-zstd-external")]
+    /// Compress using zstd with a pre-trained dictionary, see [ZstdDictionary]. The server needs the same dictionary,
+    /// its id is sent in the [crate::ZSTD_DICTIONARY_ID_HEADER] header. Only available with the
+    /// `compress-zstd-external` feature, the ruzstd encoder of `compress-zstd` doesn't support dictionaries.
+    /// Requires [`crate::zstd_external::initialize`].
+    ZstdWithDictionary { level: u8, dictionary: ZstdDictionary },
+
     #[cfg(feature = "compress-brotli")]
-    /// Compress using brotli.
-    Brotli,
+    /// Compress using brotli. `quality` is between 0 and 11, `window` is the base 2 logarithm of the window size
+    /// between 10 and 24. High qualities are slow on low-end devices, quality 5 with window 22 is a good start.
+    Brotli { quality: u32, window: u32 },
+
+    #[cfg(feature = "compress-native")]
+    /// Compress using gzip with the browser's `CompressionStream` API.
+    Gzip,
+
+    #[cfg(feature = "compress-native")]
+    /// Compress using deflate (zlib format) with the browser's `CompressionStream` API.
+    Deflate,
 }
 
 impl Compression {
+    /// Compress `payload`. Returns the `content-encoding` of the result together with the compressed bytes.
+    #[cfg_attr(
+        not(any(
+            feature = "compress-zstd",
+            feature = "compress-zstd-external",
+            feature = "compress-brotli",
+            feature = "compress-native"
+        )),
+        allow(unused_variables)
+    )]
+    pub(crate) async fn compress(self, payload: &bytes::Bytes) -> std::io::Result<(&'static str, bytes::Bytes)> {
+        match self {
+            #[cfg(feature = "compress-zstd")]
+            Compression::Zstd { level } => Ok(("zstd", Self::compress_zstd(payload, level))),
+            #[cfg(all(feature = "compress-zstd-external", not(feature = "compress-zstd")))]
+            Compression::Zstd { level } => Ok(("zstd", Self::compress_zstd_external(payload, level)?)),
+            #[cfg(feature = "compress-zstd-external")]
+            Compression::ZstdWithDictionary { level, dictionary } => Ok((
+                "zstd",
+                Self::compress_zstd_external_with_dictionary(payload, level, dictionary.data)?,
+            )),
+            #[cfg(feature = "compress-brotli")]
+            Compression::Brotli { quality, window } => Ok(("br", Self::compress_br(payload, quality, window)?)),
+            #[cfg(feature = "compress-native")]
+            Compression::Gzip => Ok(("gzip", Self::compress_native(payload, "gzip").await?)),
+            #[cfg(feature = "compress-native")]
+            Compression::Deflate => Ok(("deflate", Self::compress_native(payload, "deflate").await?)),
+        }
+    }
+
+    /// The `content-encoding` of payloads compressed with this compression.
+    #[cfg(feature = "compression-worker")]
+    pub(crate) fn content_encoding(self) -> &'static str {
+        match self {
+            #[cfg(any(feature = "compress-zstd", feature = "compress-zstd-external"))]
+            Compression::Zstd { .. } => "zstd",
+            #[cfg(feature = "compress-zstd-external")]
+            Compression::ZstdWithDictionary { .. } => "zstd",
+            #[cfg(feature = "compress-brotli")]
+            Compression::Brotli { .. } => "br",
+            #[cfg(feature = "compress-native")]
+            Compression::Gzip => "gzip",
+            #[cfg(feature = "compress-native")]
+            Compression::Deflate => "deflate",
+        }
+    }
+
+    /// The level of compressions that have one, see [AdaptiveCompression].
+    pub(crate) fn level(self) -> Option<u32> {
+        match self {
+            #[cfg(any(feature = "compress-zstd", feature = "compress-zstd-external"))]
+            Compression::Zstd { level } => Some(level as u32),
+            #[cfg(feature = "compress-zstd-external")]
+            Compression::ZstdWithDictionary { level, .. } => Some(level as u32),
+            #[cfg(feature = "compress-brotli")]
+            Compression::Brotli { quality, .. } => Some(quality),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    /// The same compression with `level`, clamped to the supported range.
+    #[cfg_attr(
+        not(any(
+            feature = "compress-zstd",
+            feature = "compress-zstd-external",
+            feature = "compress-brotli"
+        )),
+        allow(unused_variables)
+    )]
+    pub(crate) fn with_level(self, level: u32) -> Self {
+        match self {
+            #[cfg(any(feature = "compress-zstd", feature = "compress-zstd-external"))]
+            Compression::Zstd { .. } => Compression::Zstd {
+                level: level.clamp(1, 22) as u8,
+            },
+            #[cfg(feature = "compress-zstd-external")]
+            Compression::ZstdWithDictionary { dictionary, .. } => Compression::ZstdWithDictionary {
+                level: level.clamp(1, 22) as u8,
+                dictionary,
+            },
+            #[cfg(feature = "compress-brotli")]
+            Compression::Brotli { window, .. } => Compression::Brotli {
+                quality: level.min(11),
+                window,
+            },
+            #[allow(unreachable_patterns)]
+            compression => compression,
+        }
+    }
+
+    /// The id of the dictionary the payload is compressed with, sent in the [crate::ZSTD_DICTIONARY_ID_HEADER] header.
+    pub(crate) fn dictionary_id(self) -> Option<u32> {
+        match self {
+            #[cfg(feature = "compress-zstd-external")]
+            Compression::ZstdWithDictionary { dictionary, .. } => Some(dictionary.id),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
     #[cfg(feature = "compress-brotli")]
-    pub fn compress_br(payload: &bytes::Bytes) -> std::io::Result<bytes::Bytes> {
+    pub fn compress_br(payload: &bytes::Bytes, quality: u32, window: u32) -> std::io::Result<bytes::Bytes> {
         let mut compressed = Vec::new();
         {
             use std::io::Write as _;
-            let mut writer = brotli::CompressorWriter::new(&mut compressed, 4096, 11, 22);
+            let mut writer =
+                brotli::CompressorWriter::new(&mut compressed, 4096, quality.min(11), window.clamp(10, 24));
             writer
                 .write_all(payload)
                 .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
@@ -24,6 +144,58 @@ impl Compression {
         Ok(bytes::Bytes::from(compressed))
     }
 
+    /// Compress with the browser's
+    /// [`CompressionStream`](https://developer.mozilla.org/en-US/docs/Web/API/CompressionStream) API. `format` is `gzip`, `deflate` or `deflate-raw`. The browser may compress off the main thread and no code
+    /// needs to be `eval`-loaded, so this works with a strict content security policy.
+    #[cfg(feature = "compress-native")]
+    pub async fn compress_native(payload: &bytes::Bytes, format: &str) -> std::io::Result<bytes::Bytes> {
+        use wasm_bindgen::prelude::*;
+        use web_sys::{
+            js_sys::{
+                Array,
+                Uint8Array,
+            },
+            Blob,
+            ReadableWritablePair,
+            Response,
+        };
+
+        // Declared here since web-sys only provides CompressionStream with `web_sys_unstable_apis`.
+        #[wasm_bindgen]
+        extern "C" {
+            type CompressionStream;
+
+            #[wasm_bindgen(constructor, catch)]
+            fn new(format: &str) -> Result<CompressionStream, JsValue>;
+        }
+
+        let err = |err: JsValue| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{err:?}"));
+
+        let compression_stream = CompressionStream::new(format).map_err(err)?;
+        let blob = Blob::new_with_u8_array_sequence(&Array::of1(&Uint8Array::from(payload.as_ref()))).map_err(err)?;
+        let compressed = blob
+            .stream()
+            .pipe_through(compression_stream.unchecked_ref::<ReadableWritablePair>());
+        let response = Response::new_with_opt_readable_stream(Some(&compressed)).map_err(err)?;
+        let buffer = wasm_bindgen_futures::JsFuture::from(response.array_buffer().map_err(err)?)
+            .await
+            .map_err(err)?;
+        Ok(bytes::Bytes::from(Uint8Array::new(&buffer).to_vec()))
+    }
+
+    /// Compress with the pure Rust [ruzstd](https://github.com/KillingSpark/zstd-rs) encoder. No JS and no
+    /// initialization is needed. ruzstd only implements its fastest level so far (comparable to zstd level 1), `level`
+    /// is accepted for compatibility with [Self::compress_zstd_external].
+    #[cfg(feature = "compress-zstd")]
+    pub fn compress_zstd(payload: &bytes::Bytes, level: u8) -> bytes::Bytes {
+        use ruzstd::encoding::CompressionLevel;
+        let level = match level {
+            0 => CompressionLevel::Uncompressed,
+            _ => CompressionLevel::Fastest,
+        };
+        bytes::Bytes::from(ruzstd::encoding::compress_to_vec(payload.as_ref(), level))
+    }
+
     #[cfg(feature = "compress-zstd-external")]
     pub fn compress_zstd_external(payload: &bytes::Bytes, level: u8) -> std::io::Result<bytes::Bytes> {
         use wasm_bindgen::prelude::*;
@@ -38,4 +210,132 @@ impl Compression {
         let compressed = compress(Uint8Array::from(payload.as_ref()), level as _);
         Ok(bytes::Bytes::from(compressed.to_vec()))
     }
+
+    #[cfg(feature = "compress-zstd-external")]
+    pub fn compress_zstd_external_with_dictionary(
+        payload: &bytes::Bytes,
+        level: u8,
+        dictionary: &[u8],
+    ) -> std::io::Result<bytes::Bytes> {
+        use wasm_bindgen::prelude::*;
+        use web_sys::js_sys::Uint8Array;
+
+        #[wasm_bindgen]
+        extern "C" {
+            #[wasm_bindgen(js_namespace = zstd, js_name = createCCtx)]
+            fn create_cctx() -> JsValue;
+            #[wasm_bindgen(js_namespace = zstd, js_name = freeCCtx)]
+            fn free_cctx(cctx: &JsValue);
+            #[wasm_bindgen(js_namespace = zstd, js_name = compressUsingDict, catch)]
+            fn compress_using_dict(
+                cctx: &JsValue,
+                buf: Uint8Array,
+                dict: Uint8Array,
+                level: u32,
+            ) -> Result<Uint8Array, JsValue>;
+        }
+
+        let cctx = create_cctx();
+        let compressed = compress_using_dict(
+            &cctx,
+            Uint8Array::from(payload.as_ref()),
+            Uint8Array::from(dictionary),
+            level as _,
+        );
+        free_cctx(&cctx);
+        let compressed =
+            compressed.map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{err:?}")))?;
+        Ok(bytes::Bytes::from(compressed.to_vec()))
+    }
+}
+
+/// A zstd dictionary, trained on captured payloads with e.g. the `train-zstd-dictionary` tool. Use it with
+/// [Compression::ZstdWithDictionary].
+#[cfg(feature = "compress-zstd-external")]
+#[derive(Debug, Clone, Copy)]
+pub struct ZstdDictionary {
+    id: u32,
+    data: &'static [u8],
+}
+
+#[cfg(feature = "compress-zstd-external")]
+impl ZstdDictionary {
+    /// Returns `None` if `data` is not a zstd dictionary with an id, the server needs the id to pick the dictionary.
+    /// Typically used with `include_bytes!`.
+    pub fn new(data: &'static [u8]) -> Option<Self> {
+        let id = metrics_exporter_wasm_core::zstd_dictionary_id(data)?;
+        Some(Self { id, data })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn data(&self) -> &'static [u8] {
+        self.data
+    }
+}
+
+/// Adjusts the level of [Compression::Brotli] and [Compression::Zstd] to the time compression takes on the device and
+/// the ratio it achieves, see [crate::HttpPostTransport::adaptive_compression].
+///
+/// When compressing a payload on the main thread takes longer than [Self::max_time], or the payload shrinks to more
+/// than [Self::max_ratio] of its size so a higher level doesn't pay off, the level is lowered by one for the next
+/// payload. When it takes less than a quarter of [Self::max_time], the level is raised again, up to the configured
+/// level.
+///
+/// Payloads compressed by a `CompressionWorker` (feature `compression-worker`) don't block the main thread and count
+/// as taking no time, only their ratio lowers the level. The round trip to the worker is not measured.
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveCompression {
+    pub max_time: std::time::Duration,
+    /// The compressed size divided by the uncompressed size above which compressing isn't worth a higher level. 0.9 by
+    /// default.
+    pub max_ratio: f64,
+}
+
+impl AdaptiveCompression {
+    pub fn new(max_time: std::time::Duration) -> Self {
+        Self {
+            max_time,
+            max_ratio: 0.9,
+        }
+    }
+
+    pub fn max_ratio(mut self, max_ratio: f64) -> Self {
+        self.max_ratio = max_ratio;
+        self
+    }
+
+    /// The level to use after compressing with `level` took `elapsed` and achieved `ratio` (compressed size divided by
+    /// uncompressed size).
+    pub(crate) fn next_level(&self, level: u32, max_level: u32, elapsed: std::time::Duration, ratio: f64) -> u32 {
+        if elapsed > self.max_time || ratio > self.max_ratio {
+            level.saturating_sub(1)
+        } else if elapsed < self.max_time / 4 {
+            (level + 1).min(max_level)
+        } else {
+            level
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn adaptive_compression_levels() {
+        let adaptive = AdaptiveCompression::new(Duration::from_millis(20));
+        let fast = Duration::from_millis(1);
+
+        assert_eq!(adaptive.next_level(5, 5, Duration::from_millis(30), 0.3), 4);
+        assert_eq!(adaptive.next_level(4, 5, fast, 0.3), 5);
+        assert_eq!(adaptive.next_level(5, 5, fast, 0.3), 5);
+        assert_eq!(adaptive.next_level(4, 5, Duration::from_millis(10), 0.3), 4);
+        // Payloads that hardly compress don't get a higher level, however fast compression is.
+        assert_eq!(adaptive.next_level(4, 5, fast, 0.95), 3);
+        assert_eq!(adaptive.next_level(0, 5, fast, 0.95), 0);
+    }
 }
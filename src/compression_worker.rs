@@ -0,0 +1,612 @@
+//! Encode and compress payloads in a dedicated Web Worker instead of the main thread, see [CompressionWorker].
+
+use crate::Compression;
+use bytes::Bytes;
+use metrics_exporter_wasm_core::{
+    transfer,
+    ClientSession,
+    PayloadMetadata,
+    PayloadVersion,
+    RecordedEvents,
+};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    io,
+    rc::Rc,
+    time::Duration,
+};
+use tokio::sync::oneshot;
+use wasm_bindgen::prelude::*;
+use web_sys::{
+    js_sys::{
+        self,
+        Array,
+        Uint8Array,
+    },
+    DedicatedWorkerGlobalScope,
+    MessageEvent,
+    Worker,
+    WorkerOptions,
+    WorkerType,
+};
+
+const READY: &str = "ready";
+
+/// How long to wait for the worker by default, see [CompressionWorker::timeout].
+pub const DEFAULT_WORKER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Runs encoding and compression in a dedicated Web Worker, see [crate::Asn1Encoder::worker] and
+/// [crate::HttpPostTransport::compression_worker]. Encoding large batches and high brotli qualities can take long
+/// enough on low-end devices to cause visible jank when run on the main thread.
+///
+/// The worker runs the same wasm module as the app. `script_url` is a module script that initializes the module and
+/// calls [compression_worker_main]. With `wasm-bindgen --target web` output that is:
+///
+/// ```js
+/// import init, { metricsExporterCompressionWorker } from "./app.js";
+/// await init();
+/// await metricsExporterCompressionWorker();
+/// ```
+///
+/// Note that a `#[wasm_bindgen(start)]` function of the app runs in the worker as well.
+///
+/// Batches are copied to the worker in a byte aligned format, which costs a fraction of UPER encoding them. Payloads
+/// are encoded and compressed on the main thread while the worker is starting, when it fails and when it doesn't answer
+/// within [Self::timeout]. A worker that timed out is terminated and not used again.
+pub struct CompressionWorker {
+    worker: Worker,
+    timeout: Duration,
+    state: Rc<RefCell<State>>,
+    _onmessage: Closure<dyn FnMut(MessageEvent)>,
+    _onerror: Closure<dyn FnMut(web_sys::Event)>,
+}
+
+#[derive(Default)]
+struct State {
+    ready: bool,
+    failed: bool,
+    next_id: u32,
+    pending: HashMap<u32, oneshot::Sender<io::Result<Bytes>>>,
+    /// The ids of the zstd dictionaries the worker has.
+    #[cfg(feature = "compress-zstd-external")]
+    dictionaries: std::collections::HashSet<u32>,
+}
+
+impl fmt::Debug for CompressionWorker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.borrow();
+        f.debug_struct("CompressionWorker")
+            .field("timeout", &self.timeout)
+            .field("ready", &state.ready)
+            .field("failed", &state.failed)
+            .field("pending", &state.pending.len())
+            .finish()
+    }
+}
+
+impl CompressionWorker {
+    /// Start the worker from the module script `script_url`.
+    pub fn new(script_url: &str) -> io::Result<Self> {
+        let options = WorkerOptions::new();
+        options.set_type(WorkerType::Module);
+        let worker = Worker::new_with_options(script_url, &options).map_err(js_err)?;
+        let state = Rc::new(RefCell::new(State::default()));
+
+        let onmessage = Closure::<dyn FnMut(MessageEvent)>::new({
+            let state = state.clone();
+            move |event: MessageEvent| {
+                let data = event.data();
+                let mut state = state.borrow_mut();
+                if data.as_string().as_deref() == Some(READY) {
+                    debug!("compression worker ready");
+                    state.ready = true;
+                    return;
+                }
+                let response = Uint8Array::new(&data).to_vec();
+                let (id, result) = match read_response(&response) {
+                    Ok(response) => response,
+                    Err(err) => {
+                        warn!(?err, "invalid message of the compression worker");
+                        return;
+                    }
+                };
+                if let Some(tx) = state.pending.remove(&id) {
+                    let _ = tx.send(result);
+                }
+            }
+        });
+        worker.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+
+        let onerror = Closure::<dyn FnMut(web_sys::Event)>::new({
+            let state = state.clone();
+            move |event: web_sys::Event| {
+                error!(?event, "compression worker failed, compressing on the main thread");
+                let mut state = state.borrow_mut();
+                state.failed = true;
+                // Dropping the senders fails the pending requests.
+                state.pending.clear();
+            }
+        });
+        worker.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+
+        Ok(Self {
+            worker,
+            timeout: DEFAULT_WORKER_TIMEOUT,
+            state,
+            _onmessage: onmessage,
+            _onerror: onerror,
+        })
+    }
+
+    /// How long to wait for the worker to encode or compress a payload before doing it on the main thread.
+    /// [DEFAULT_WORKER_TIMEOUT] by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Compress `payload` in the worker. Returns `None` if the worker isn't available, the caller compresses on the
+    /// main thread then.
+    ///
+    /// A zstd dictionary is sent to the worker once, with the first payload compressed with it. Later requests refer to
+    /// it by its id.
+    pub(crate) async fn compress(&self, compression: Compression, payload: &Bytes) -> Option<(&'static str, Bytes)> {
+        #[cfg(feature = "compress-zstd-external")]
+        if let Compression::ZstdWithDictionary { dictionary, .. } = compression {
+            if !self.add_dictionary(dictionary).await {
+                return None;
+            }
+        }
+        let compressed = self.run(|id| write_compress_request(id, compression, payload)).await?;
+        Some((compression.content_encoding(), compressed))
+    }
+
+    /// Send `dictionary` to the worker unless it has it already. Returns `false` if the worker isn't available.
+    #[cfg(feature = "compress-zstd-external")]
+    async fn add_dictionary(&self, dictionary: crate::ZstdDictionary) -> bool {
+        if self.state.borrow().dictionaries.contains(&dictionary.id()) {
+            return true;
+        }
+        if self
+            .run(|id| write_add_dictionary_request(id, dictionary))
+            .await
+            .is_none()
+        {
+            return false;
+        }
+        self.state.borrow_mut().dictionaries.insert(dictionary.id());
+        true
+    }
+
+    /// Encode `events` in the worker, see [PayloadVersion::encode_with_sequence]. Returns `None` if the worker isn't
+    /// available, the caller encodes on the main thread then.
+    pub(crate) async fn encode(
+        &self,
+        version: PayloadVersion,
+        session: Option<&ClientSession>,
+        metadata: Option<&PayloadMetadata>,
+        events: &RecordedEvents,
+    ) -> Option<Vec<u8>> {
+        self.run(|id| write_encode_request(id, version, session, metadata, events))
+            .await
+            .map(Vec::from)
+    }
+
+    /// Send the request built by `request` to the worker and wait for the response.
+    async fn run(&self, request: impl FnOnce(u32) -> Vec<u8>) -> Option<Bytes> {
+        let (id, rx) = {
+            let mut state = self.state.borrow_mut();
+            if !state.ready || state.failed {
+                return None;
+            }
+            let id = state.next_id;
+            state.next_id = state.next_id.wrapping_add(1);
+
+            let message = Uint8Array::from(request(id).as_slice());
+            if let Err(err) = self
+                .worker
+                .post_message_with_transfer(&message, &Array::of1(&message.buffer()))
+            {
+                warn!(?err, "failed to post to the compression worker");
+                return None;
+            }
+
+            let (tx, rx) = oneshot::channel();
+            state.pending.insert(id, tx);
+            (id, rx)
+        };
+
+        tokio::select! {
+            result = rx => match result {
+                Ok(Ok(payload)) => Some(payload),
+                Ok(Err(err)) => {
+                    warn!(?err, "compression worker failed, falling back to the main thread");
+                    None
+                }
+                // The worker failed, see `onerror`.
+                Err(_) => None,
+            },
+            _ = wasmtimer::tokio::sleep(self.timeout) => {
+                error!(timeout = ?self.timeout, "compression worker doesn't respond, using the main thread from now on");
+                let mut state = self.state.borrow_mut();
+                state.failed = true;
+                state.pending.remove(&id);
+                self.worker.terminate();
+                None
+            }
+        }
+    }
+}
+
+impl Drop for CompressionWorker {
+    fn drop(&mut self) {
+        self.worker.terminate();
+    }
+}
+
+/// Entry point of the [CompressionWorker], called by the worker script after the wasm module is initialized.
+#[wasm_bindgen(js_name = metricsExporterCompressionWorker)]
+pub async fn compression_worker_main() {
+    #[cfg(feature = "compress-zstd-external")]
+    crate::zstd_external::initialize().await;
+
+    let scope: DedicatedWorkerGlobalScope = js_sys::global().unchecked_into();
+    let onmessage = Closure::<dyn FnMut(MessageEvent)>::new(|event: MessageEvent| {
+        wasm_bindgen_futures::spawn_local(async move {
+            let scope: DedicatedWorkerGlobalScope = js_sys::global().unchecked_into();
+            let request = Uint8Array::new(&event.data()).to_vec();
+            let Some(response) = handle_request(&request).await else {
+                error!("invalid message to the compression worker");
+                return;
+            };
+            let response = Uint8Array::from(response.as_slice());
+            if let Err(err) = scope.post_message_with_transfer(&response, &Array::of1(&response.buffer())) {
+                error!(?err, "failed to respond from the compression worker");
+            }
+        });
+    });
+    scope.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+    onmessage.forget();
+
+    if let Err(err) = scope.post_message(&READY.into()) {
+        error!(?err, "failed to signal compression worker readiness");
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+// Messages between the main thread and the worker are byte arrays, transferred without copying:
+//
+// request:  id: u32, task: u8, then for
+//           TASK_COMPRESS: compression, payload: bytes
+//           TASK_ENCODE:   version: content type, session: option, metadata: option (session id, nonce, sequence),
+//                          events (see `transfer::write_events`)
+//           TASK_ADD_DICTIONARY: dictionary: bytes, kept by the worker and referred to by its id
+// response: id: u32, status: u8, then the payload for RESPONSE_OK or the error message for RESPONSE_ERROR
+
+const TASK_COMPRESS: u8 = 0;
+const TASK_ENCODE: u8 = 1;
+#[cfg(feature = "compress-zstd-external")]
+const TASK_ADD_DICTIONARY: u8 = 2;
+const RESPONSE_OK: u8 = 0;
+const RESPONSE_ERROR: u8 = 1;
+
+fn write_compress_request(id: u32, compression: Compression, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 32);
+    transfer::write_u32(&mut out, id);
+    out.push(TASK_COMPRESS);
+    write_compression(&mut out, compression);
+    transfer::write_bytes(&mut out, payload);
+    out
+}
+
+#[cfg(feature = "compress-zstd-external")]
+fn write_add_dictionary_request(id: u32, dictionary: crate::ZstdDictionary) -> Vec<u8> {
+    let mut out = Vec::with_capacity(dictionary.data().len() + 16);
+    transfer::write_u32(&mut out, id);
+    out.push(TASK_ADD_DICTIONARY);
+    transfer::write_bytes(&mut out, dictionary.data());
+    out
+}
+
+fn write_encode_request(
+    id: u32,
+    version: PayloadVersion,
+    session: Option<&ClientSession>,
+    metadata: Option<&PayloadMetadata>,
+    events: &RecordedEvents,
+) -> Vec<u8> {
+    let mut out = Vec::new();
+    transfer::write_u32(&mut out, id);
+    out.push(TASK_ENCODE);
+    transfer::write_str(&mut out, version.content_type());
+    match session {
+        Some(session) => {
+            out.push(1);
+            transfer::write_session(&mut out, session);
+        }
+        None => out.push(0),
+    }
+    match metadata {
+        Some(metadata) => {
+            out.push(1);
+            transfer::write_str(&mut out, &metadata.session_id);
+            transfer::write_str(&mut out, &metadata.nonce);
+            transfer::write_u64(&mut out, metadata.sequence);
+        }
+        None => out.push(0),
+    }
+    transfer::write_events(&mut out, events);
+    out
+}
+
+/// Runs a request in the worker. Returns `None` if the request has no id to respond to.
+async fn handle_request(request: &[u8]) -> Option<Vec<u8>> {
+    let mut input = request;
+    let id = transfer::read_u32(&mut input).ok()?;
+    let result = match transfer::read_u8(&mut input).ok()? {
+        TASK_COMPRESS => compress_request(input).await,
+        TASK_ENCODE => encode_request(input),
+        #[cfg(feature = "compress-zstd-external")]
+        TASK_ADD_DICTIONARY => add_dictionary_request(input),
+        task => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("unknown compression worker task {task}"),
+        )),
+    };
+    let mut out = Vec::new();
+    transfer::write_u32(&mut out, id);
+    match result {
+        Ok(payload) => {
+            out.push(RESPONSE_OK);
+            out.extend_from_slice(&payload);
+        }
+        Err(err) => {
+            out.push(RESPONSE_ERROR);
+            out.extend_from_slice(err.to_string().as_bytes());
+        }
+    }
+    Some(out)
+}
+
+async fn compress_request(mut input: &[u8]) -> io::Result<Vec<u8>> {
+    let compression = read_compression(&mut input)?;
+    let payload = Bytes::copy_from_slice(transfer::read_bytes(&mut input)?);
+    let (_, compressed) = compression.compress(&payload).await?;
+    Ok(compressed.into())
+}
+
+fn encode_request(mut input: &[u8]) -> io::Result<Vec<u8>> {
+    let version = PayloadVersion::from_content_type(Some(&transfer::read_string(&mut input)?))?;
+    let session = match transfer::read_u8(&mut input)? {
+        0 => None,
+        _ => Some(transfer::read_session(&mut input)?),
+    };
+    let metadata = match transfer::read_u8(&mut input)? {
+        0 => None,
+        _ => Some(PayloadMetadata {
+            session_id: transfer::read_string(&mut input)?,
+            nonce: transfer::read_string(&mut input)?,
+            sequence: transfer::read_u64(&mut input)?,
+        }),
+    };
+    let events = transfer::read_events(&mut input)?;
+    version.encode_with_sequence(&events, session.as_ref(), metadata.as_ref())
+}
+
+#[cfg(feature = "compress-zstd-external")]
+fn add_dictionary_request(mut input: &[u8]) -> io::Result<Vec<u8>> {
+    let data = transfer::read_bytes(&mut input)?;
+    let not_a_dictionary = || io::Error::new(io::ErrorKind::InvalidInput, "not a zstd dictionary");
+    let id = metrics_exporter_wasm_core::zstd_dictionary_id(data).ok_or_else(not_a_dictionary)?;
+    WORKER_DICTIONARIES.with_borrow_mut(|dictionaries| {
+        if let std::collections::hash_map::Entry::Vacant(entry) = dictionaries.entry(id) {
+            // Dictionaries live as long as the worker, like the `include_bytes!` ones of the main thread.
+            let data = Box::leak(data.to_vec().into_boxed_slice());
+            entry.insert(crate::ZstdDictionary::new(data).ok_or_else(not_a_dictionary)?);
+        }
+        Ok(Vec::new())
+    })
+}
+
+fn read_response(response: &[u8]) -> io::Result<(u32, io::Result<Bytes>)> {
+    let mut input = response;
+    let id = transfer::read_u32(&mut input)?;
+    let result = match transfer::read_u8(&mut input)? {
+        RESPONSE_OK => Ok(Bytes::copy_from_slice(input)),
+        RESPONSE_ERROR => Err(io::Error::other(String::from_utf8_lossy(input).into_owned())),
+        status => return Err(invalid(format!("unknown response status {status}"))),
+    };
+    Ok((id, result))
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+#[cfg(any(feature = "compress-zstd", feature = "compress-zstd-external"))]
+const COMPRESSION_ZSTD: u8 = 0;
+#[cfg(feature = "compress-zstd-external")]
+const COMPRESSION_ZSTD_DICTIONARY: u8 = 1;
+#[cfg(feature = "compress-brotli")]
+const COMPRESSION_BROTLI: u8 = 2;
+#[cfg(feature = "compress-native")]
+const COMPRESSION_GZIP: u8 = 3;
+#[cfg(feature = "compress-native")]
+const COMPRESSION_DEFLATE: u8 = 4;
+
+/// [Compression] as its kind, followed by its parameters.
+#[cfg_attr(
+    not(any(
+        feature = "compress-zstd",
+        feature = "compress-zstd-external",
+        feature = "compress-brotli",
+        feature = "compress-native"
+    )),
+    allow(unused_variables)
+)]
+fn write_compression(out: &mut Vec<u8>, compression: Compression) {
+    match compression {
+        #[cfg(any(feature = "compress-zstd", feature = "compress-zstd-external"))]
+        Compression::Zstd { level } => {
+            out.push(COMPRESSION_ZSTD);
+            out.push(level);
+        }
+        #[cfg(feature = "compress-zstd-external")]
+        Compression::ZstdWithDictionary { level, dictionary } => {
+            out.push(COMPRESSION_ZSTD_DICTIONARY);
+            out.push(level);
+            transfer::write_u32(out, dictionary.id());
+        }
+        #[cfg(feature = "compress-brotli")]
+        Compression::Brotli { quality, window } => {
+            out.push(COMPRESSION_BROTLI);
+            transfer::write_u32(out, quality);
+            transfer::write_u32(out, window);
+        }
+        #[cfg(feature = "compress-native")]
+        Compression::Gzip => out.push(COMPRESSION_GZIP),
+        #[cfg(feature = "compress-native")]
+        Compression::Deflate => out.push(COMPRESSION_DEFLATE),
+    }
+}
+
+fn read_compression(input: &mut &[u8]) -> io::Result<Compression> {
+    match transfer::read_u8(input)? {
+        #[cfg(any(feature = "compress-zstd", feature = "compress-zstd-external"))]
+        COMPRESSION_ZSTD => Ok(Compression::Zstd {
+            level: transfer::read_u8(input)?,
+        }),
+        #[cfg(feature = "compress-zstd-external")]
+        COMPRESSION_ZSTD_DICTIONARY => {
+            let level = transfer::read_u8(input)?;
+            let id = transfer::read_u32(input)?;
+            let dictionary = WORKER_DICTIONARIES
+                .with_borrow(|dictionaries| dictionaries.get(&id).copied())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("unknown zstd dictionary {id}")))?;
+            Ok(Compression::ZstdWithDictionary { level, dictionary })
+        }
+        #[cfg(feature = "compress-brotli")]
+        COMPRESSION_BROTLI => Ok(Compression::Brotli {
+            quality: transfer::read_u32(input)?,
+            window: transfer::read_u32(input)?,
+        }),
+        #[cfg(feature = "compress-native")]
+        COMPRESSION_GZIP => Ok(Compression::Gzip),
+        #[cfg(feature = "compress-native")]
+        COMPRESSION_DEFLATE => Ok(Compression::Deflate),
+        kind => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("compression {kind} is not enabled in the compression worker"),
+        )),
+    }
+}
+
+#[cfg(feature = "compress-zstd-external")]
+thread_local! {
+    /// The dictionaries sent with [TASK_ADD_DICTIONARY], by id.
+    static WORKER_DICTIONARIES: RefCell<HashMap<u32, crate::ZstdDictionary>> = RefCell::new(HashMap::new());
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn js_err(err: JsValue) -> io::Error {
+    io::Error::other(format!("{err:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use metrics_exporter_wasm_core::{
+        Event,
+        MetricOperation,
+    };
+
+    fn events() -> RecordedEvents {
+        RecordedEvents::from(vec![Event::Metric {
+            key: metrics::Key::from_parts("requests", &[("route", "/api")]),
+            op: MetricOperation::IncrementCounter(1),
+        }])
+    }
+
+    #[test]
+    fn encode_request_and_response() {
+        let events = events();
+        let session = ClientSession::new().session_id("session");
+        let metadata = PayloadMetadata {
+            session_id: "session".to_string(),
+            nonce: "nonce".to_string(),
+            sequence: 3,
+        };
+        let request = write_encode_request(7, PayloadVersion::V1, Some(&session), Some(&metadata), &events);
+
+        let response = block_on(handle_request(&request)).unwrap();
+        let (id, payload) = read_response(&response).unwrap();
+        assert_eq!(id, 7);
+        assert_eq!(
+            payload.unwrap(),
+            PayloadVersion::V1
+                .encode_with_sequence(&events, Some(&session), Some(&metadata))
+                .unwrap()
+        );
+    }
+
+    #[cfg(feature = "compress-brotli")]
+    #[test]
+    fn compress_request_and_response() {
+        let payload = Bytes::from(vec![42; 1024]);
+        let compression = Compression::Brotli { quality: 5, window: 22 };
+        let request = write_compress_request(1, compression, &payload);
+
+        let response = block_on(handle_request(&request)).unwrap();
+        let (id, compressed) = read_response(&response).unwrap();
+        assert_eq!(id, 1);
+        assert_eq!(compressed.unwrap(), block_on(compression.compress(&payload)).unwrap().1);
+    }
+
+    #[cfg(feature = "compress-zstd-external")]
+    #[test]
+    fn dictionaries_are_referred_to_by_id() {
+        static DICTIONARY: [u8; 12] = [0x37, 0xa4, 0x30, 0xec, 7, 0, 0, 0, 1, 2, 3, 4];
+        let dictionary = crate::ZstdDictionary::new(&DICTIONARY).unwrap();
+        let compression = Compression::ZstdWithDictionary { level: 3, dictionary };
+        let mut out = Vec::new();
+        write_compression(&mut out, compression);
+        assert!(out.len() < DICTIONARY.len());
+
+        let err = read_compression(&mut out.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        let response = block_on(handle_request(&write_add_dictionary_request(1, dictionary))).unwrap();
+        assert!(read_response(&response).unwrap().1.is_ok());
+        let Compression::ZstdWithDictionary { level, dictionary } = read_compression(&mut out.as_slice()).unwrap()
+        else {
+            panic!("expected a dictionary compression");
+        };
+        assert_eq!((level, dictionary.id(), dictionary.data()), (3, 7, &DICTIONARY[..]));
+    }
+
+    #[test]
+    fn errors_are_responses() {
+        let mut request = Vec::new();
+        transfer::write_u32(&mut request, u32::MAX);
+        request.push(42);
+        let response = block_on(handle_request(&request)).unwrap();
+        let (id, result) = read_response(&response).unwrap();
+        assert_eq!(id, u32::MAX);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("unknown compression worker task 42"));
+
+        // Truncated requests get an error response as long as they have an id.
+        let request = write_encode_request(2, PayloadVersion::V0, None, None, &events());
+        let response = block_on(handle_request(&request[..request.len() - 1])).unwrap();
+        assert!(read_response(&response).unwrap().1.is_err());
+        assert!(block_on(handle_request(&request[..3])).is_none());
+
+        assert!(read_response(&[0, 0, 0, 0, 2]).is_err());
+    }
+}
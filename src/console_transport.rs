@@ -0,0 +1,147 @@
+use crate::{
+    Compression,
+    DecodeLimits,
+    Event,
+    MetricOperation,
+    PayloadVersion,
+    RecordedEvent,
+    Transport,
+    TransportError,
+};
+use bytes::Bytes;
+use metrics::Key;
+use std::collections::BTreeMap;
+use wasm_bindgen::JsValue;
+use web_sys::{
+    console,
+    js_sys::{
+        Array,
+        Object,
+        Reflect,
+    },
+};
+
+/// A [Transport] for development that logs what would be sent to the browser console instead of sending it.
+///
+/// Every payload is decoded back into [RecordedEvent]s and logged as a collapsed console group with one table per
+/// metric key. This requires the [crate::Asn1Encoder], the [PayloadVersion] is taken
+/// from the content type of the encoder.
+#[derive(Debug, Default)]
+pub struct ConsoleTransport {
+    compression: Option<Compression>,
+    version: PayloadVersion,
+}
+
+impl ConsoleTransport {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Compress the payloads with `compression` to log the size they would have when sent with
+    /// [crate::HttpPostTransport::compression].
+    pub fn compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+}
+
+impl Transport for ConsoleTransport {
+    fn set_content_type(&mut self, content_type: &'static str) {
+        if let Ok(version) = PayloadVersion::from_content_type(Some(content_type)) {
+            self.version = version;
+        }
+    }
+
+    async fn send(&self, payload: &Bytes) -> Result<(), TransportError> {
+        let events = self
+            .version
+            .decode(payload, &DecodeLimits::UNLIMITED)
+            .and_then(Vec::try_from);
+        let events: Vec<RecordedEvent> = match events {
+            Ok(events) => events,
+            Err(err) => {
+                // Retrying won't help, so the payload is dropped.
+                console::warn_1(&format!("metrics payload of {} bytes can't be decoded: {err}", payload.len()).into());
+                return Ok(());
+            }
+        };
+
+        let mut size = format!("{} bytes", payload.len());
+        if let Some(compression) = self.compression {
+            let (content_encoding, compressed) =
+                compression.compress(payload).await.map_err(TransportError::Compress)?;
+            size += &format!(", {} bytes {content_encoding}", compressed.len());
+        }
+        console::group_collapsed_1(&format!("metrics payload: {} events, {size}", events.len()).into());
+
+        let mut descriptions = Vec::new();
+        let mut metrics = BTreeMap::<String, Vec<(String, MetricOperation)>>::new();
+        for RecordedEvent { timestamp, event } in events {
+            let time = timestamp.format("%H:%M:%S%.3f").to_string();
+            match event {
+                Event::Description {
+                    name,
+                    metric_type,
+                    unit,
+                    description,
+                } => descriptions.push(row(&[
+                    ("time", time.into()),
+                    ("name", name.as_str().into()),
+                    ("type", format!("{metric_type:?}").into()),
+                    ("unit", unit.map(|unit| unit.as_str()).unwrap_or_default().into()),
+                    ("description", description.as_ref().into()),
+                ])),
+                Event::Metric { key, op } => metrics.entry(format_key(&key)).or_default().push((time, op)),
+            }
+        }
+
+        if !descriptions.is_empty() {
+            console::group_collapsed_1(&"descriptions".into());
+            console::table_1(&descriptions.into_iter().collect::<Array>());
+            console::group_end();
+        }
+
+        for (key, ops) in metrics {
+            console::group_collapsed_1(&format!("{key} ({})", ops.len()).into());
+            let rows = ops
+                .into_iter()
+                .map(|(time, op)| {
+                    let (op, value) = match op {
+                        MetricOperation::IncrementCounter(value) => ("increment counter", value as f64),
+                        MetricOperation::SetCounter(value) => ("set counter", value as f64),
+                        MetricOperation::IncrementGauge(value) => ("increment gauge", value),
+                        MetricOperation::DecrementGauge(value) => ("decrement gauge", value),
+                        MetricOperation::SetGauge(value) => ("set gauge", value),
+                        MetricOperation::RecordHistogram(value) => ("record histogram", value),
+                    };
+                    row(&[("time", time.into()), ("op", op.into()), ("value", value.into())])
+                })
+                .collect::<Array>();
+            console::table_1(&rows);
+            console::group_end();
+        }
+
+        console::group_end();
+        Ok(())
+    }
+}
+
+fn format_key(key: &Key) -> String {
+    let labels = key
+        .labels()
+        .map(|label| format!("{}={}", label.key(), label.value()))
+        .collect::<Vec<_>>();
+    if labels.is_empty() {
+        key.name().to_string()
+    } else {
+        format!("{}{{{}}}", key.name(), labels.join(", "))
+    }
+}
+
+fn row(columns: &[(&str, JsValue)]) -> Object {
+    let row = Object::new();
+    for (name, value) in columns {
+        let _ = Reflect::set(&row, &(*name).into(), value);
+    }
+    row
+}
@@ -0,0 +1,101 @@
+use crate::{
+    Transport,
+    TransportError,
+};
+use bytes::Bytes;
+use metrics_exporter_wasm_core::{
+    ExporterConfig,
+    PayloadMetadata,
+};
+use std::{
+    future::Future,
+    pin::Pin,
+};
+
+/// An object safe version of [Transport], implemented for all transports. Use [BoxTransport] to choose a transport at
+/// runtime:
+///
+/// ```ignore
+/// let transport: BoxTransport = if use_websocket {
+///     WebSocketTransport::new().endpoint(ws_url).boxed()
+/// } else {
+///     HttpPostTransport::new().endpoint(url).boxed()
+/// };
+/// let _guard = MetricsHttpSender::new(transport).start_with_metrics_recorder(&recorder);
+/// ```
+pub trait DynTransport {
+    fn enable_self_metrics(&mut self, self_metrics: bool);
+
+    fn set_content_type(&mut self, content_type: &'static str);
+
+    fn send_boxed<'a>(&'a self, payload: &'a Bytes) -> Pin<Box<dyn Future<Output = Result<(), TransportError>> + 'a>>;
+
+    fn send_with_metadata_boxed<'a>(
+        &'a self,
+        payload: &'a Bytes,
+        metadata: &'a PayloadMetadata,
+    ) -> Pin<Box<dyn Future<Output = Result<(), TransportError>> + 'a>>;
+
+    fn take_remote_config(&self) -> Option<ExporterConfig>;
+
+    fn apply_remote_config(&mut self, config: &ExporterConfig);
+}
+
+impl<T: Transport> DynTransport for T {
+    fn enable_self_metrics(&mut self, self_metrics: bool) {
+        Transport::enable_self_metrics(self, self_metrics);
+    }
+
+    fn set_content_type(&mut self, content_type: &'static str) {
+        Transport::set_content_type(self, content_type);
+    }
+
+    fn send_boxed<'a>(&'a self, payload: &'a Bytes) -> Pin<Box<dyn Future<Output = Result<(), TransportError>> + 'a>> {
+        Box::pin(Transport::send(self, payload))
+    }
+
+    fn send_with_metadata_boxed<'a>(
+        &'a self,
+        payload: &'a Bytes,
+        metadata: &'a PayloadMetadata,
+    ) -> Pin<Box<dyn Future<Output = Result<(), TransportError>> + 'a>> {
+        Box::pin(Transport::send_with_metadata(self, payload, metadata))
+    }
+
+    fn take_remote_config(&self) -> Option<ExporterConfig> {
+        Transport::take_remote_config(self)
+    }
+
+    fn apply_remote_config(&mut self, config: &ExporterConfig) {
+        Transport::apply_remote_config(self, config);
+    }
+}
+
+/// A boxed transport, see [DynTransport].
+pub type BoxTransport = Box<dyn DynTransport>;
+
+impl Transport for BoxTransport {
+    fn enable_self_metrics(&mut self, self_metrics: bool) {
+        (**self).enable_self_metrics(self_metrics);
+    }
+
+    fn set_content_type(&mut self, content_type: &'static str) {
+        (**self).set_content_type(content_type);
+    }
+
+    async fn send(&self, payload: &Bytes) -> Result<(), TransportError> {
+        (**self).send_boxed(payload).await
+    }
+
+    async fn send_with_metadata(&self, payload: &Bytes, metadata: &PayloadMetadata) -> Result<(), TransportError> {
+        (**self).send_with_metadata_boxed(payload, metadata).await
+    }
+
+    fn take_remote_config(&self) -> Option<ExporterConfig> {
+        (**self).take_remote_config()
+    }
+
+    fn apply_remote_config(&mut self, config: &ExporterConfig) {
+        (**self).apply_remote_config(config);
+    }
+}
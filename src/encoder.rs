@@ -0,0 +1,125 @@
+use metrics_exporter_wasm_core::{
+    ClientSession,
+    PayloadMetadata,
+    PayloadVersion,
+    RecordedEvents,
+};
+use std::{
+    future::Future,
+    io,
+};
+
+/// Encodes completed batches (see [crate::Batch::CompletedBatch]) into the payload that is handed to the
+/// [crate::Transport].
+pub trait Encoder<T> {
+    /// The content type of the encoded payload. Transports like [crate::HttpPostTransport] use it as `content-type`
+    /// header.
+    fn content_type(&self) -> &'static str;
+
+    /// Encode a batch. This must not change the state of the encoder: the [crate::MetricsHttpSender] encodes the
+    /// events of a batch again when the payload turns out to be too large and has to be split.
+    fn encode(&self, batch: &T) -> io::Result<Vec<u8>>;
+
+    /// Like [Self::encode], but may encode off the main thread, see [Asn1Encoder::worker]. This is what the
+    /// [crate::MetricsHttpSender] calls.
+    fn encode_async(&self, batch: &T) -> impl Future<Output = io::Result<Vec<u8>>> {
+        std::future::ready(self.encode(batch))
+    }
+
+    /// Called by the [crate::MetricsHttpSender] once the payload encoded from `batch` was sent. Encoders that keep
+    /// state across batches, like [crate::otlp::OtlpConverter], update it here.
+    fn commit(&mut self, _batch: &T) {}
+
+    /// Called once by the [crate::MetricsHttpSender] with the session of [crate::MetricsHttpSender::session].
+    /// Encoders whose format can't carry a session ignore it.
+    fn set_session(&mut self, _session: &ClientSession) {}
+
+    /// Called by the [crate::MetricsHttpSender] before every [Self::encode] with the sequence number and nonce of the
+    /// payload. Encoders whose format can't carry them ignore them.
+    fn set_sequence(&mut self, _metadata: &PayloadMetadata) {}
+}
+
+/// Encodes batches using ASN.1 UPER in a [PayloadVersion], with the content type of that version. This is the default
+/// encoder and what [metrics_exporter_wasm_core::decode_payload] expects on the receiving side.
+///
+/// Batches are encoded in [PayloadVersion::V0] by default, which every server understands. Opt into
+/// [PayloadVersion::V1] with [Asn1Encoder::version] once the server decodes it, only then the [ClientSession] and the
+/// sequence number are attached to every payload.
+#[derive(Debug, Default, Clone)]
+pub struct Asn1Encoder {
+    version: PayloadVersion,
+    session: Option<ClientSession>,
+    sequence: Option<PayloadMetadata>,
+    #[cfg(feature = "compression-worker")]
+    worker: Option<std::rc::Rc<crate::CompressionWorker>>,
+}
+
+impl Asn1Encoder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Encode batches in `version`. Servers of 0.4.1 and older only decode [PayloadVersion::V0].
+    pub fn version(mut self, version: PayloadVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Encode batches in `worker` instead of the main thread. Share the worker with
+    /// [crate::HttpPostTransport::compression_worker] to compress there as well.
+    #[cfg(feature = "compression-worker")]
+    pub fn worker(mut self, worker: std::rc::Rc<crate::CompressionWorker>) -> Self {
+        self.worker = Some(worker);
+        self
+    }
+}
+
+impl Encoder<RecordedEvents> for Asn1Encoder {
+    fn content_type(&self) -> &'static str {
+        self.version.content_type()
+    }
+
+    fn encode(&self, batch: &RecordedEvents) -> io::Result<Vec<u8>> {
+        self.version
+            .encode_with_sequence(batch, self.session.as_ref(), self.sequence.as_ref())
+    }
+
+    async fn encode_async(&self, batch: &RecordedEvents) -> io::Result<Vec<u8>> {
+        #[cfg(feature = "compression-worker")]
+        if let Some(worker) = &self.worker {
+            let encoded = worker
+                .encode(self.version, self.session.as_ref(), self.sequence.as_ref(), batch)
+                .await;
+            if let Some(encoded) = encoded {
+                return Ok(encoded);
+            }
+        }
+        self.encode(batch)
+    }
+
+    fn set_session(&mut self, session: &ClientSession) {
+        self.session = Some(session.clone());
+    }
+
+    fn set_sequence(&mut self, metadata: &PayloadMetadata) {
+        self.sequence = Some(metadata.clone());
+    }
+}
+
+/// Encodes [crate::RecordedEvents] as a JSON array of [crate::RecordedEvent]s. The receiving side can deserialize it
+/// into a `Vec<RecordedEvent>`.
+#[cfg(feature = "serde")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonEncoder;
+
+#[cfg(feature = "serde")]
+impl Encoder<crate::RecordedEvents> for JsonEncoder {
+    fn content_type(&self) -> &'static str {
+        "application/json"
+    }
+
+    fn encode(&self, batch: &crate::RecordedEvents) -> io::Result<Vec<u8>> {
+        let events: Vec<crate::RecordedEvent> = batch.clone().try_into()?;
+        Ok(serde_json::to_vec(&events)?)
+    }
+}
@@ -1,24 +1,160 @@
-use crate::Compression;
+use crate::{
+    transport_error::parse_retry_after,
+    AdaptiveCompression,
+    Compression,
+    HttpError,
+    TransportError,
+};
 use bytes::Bytes;
 use gloo::net::http::{
     Headers,
-    Method,
-    RequestBuilder,
+    Request,
+    Response,
+};
+use metrics_exporter_wasm_core::{
+    Asn1Decode as _,
+    ExporterConfig,
+    PayloadCompression,
+    PayloadMetadata,
+    PayloadVersion,
+    EXPORTER_CONFIG_CONTENT_TYPE,
+    IDEMPOTENCY_KEY_HEADER,
+    ZSTD_DICTIONARY_ID_HEADER,
 };
 use std::{
+    cell::{
+        Cell,
+        RefCell,
+    },
     future::Future,
     io,
+    pin::Pin,
+    rc::Rc,
     time::Duration,
 };
+use wasm_bindgen::JsValue;
+use wasmtimer::std::Instant;
 use web_sys::{
+    js_sys::{
+        Reflect,
+        Uint8Array,
+    },
     AbortController,
+    AbortSignal,
+    ReferrerPolicy,
     RequestCredentials,
+    RequestInit,
+    RequestMode,
 };
 
 pub trait Transport {
     fn enable_self_metrics(&mut self, _self_metrics: bool) {}
 
-    fn send(&self, payload: &Bytes) -> impl Future<Output = io::Result<()>>;
+    /// Called with the content type of the [crate::Encoder] before the first payload is sent.
+    fn set_content_type(&mut self, _content_type: &'static str) {}
+
+    fn send(&self, payload: &Bytes) -> impl Future<Output = Result<(), TransportError>>;
+
+    /// Send a payload together with its [PayloadMetadata], used by the [crate::MetricsHttpSender]. Transports with
+    /// per-request headers can send [PayloadMetadata::idempotency_key] in the [IDEMPOTENCY_KEY_HEADER] header (see
+    /// [HttpPostTransport::idempotency_key_header]), the default ignores the metadata.
+    fn send_with_metadata(
+        &self,
+        payload: &Bytes,
+        metadata: &PayloadMetadata,
+    ) -> impl Future<Output = Result<(), TransportError>> {
+        let _ = metadata;
+        self.send(payload)
+    }
+
+    /// Returns the [ExporterConfig] the server sent since the last call, if any. Called after every sent batch.
+    fn take_remote_config(&self) -> Option<ExporterConfig> {
+        None
+    }
+
+    /// Apply the transport related parts of an [ExporterConfig] returned by [Self::take_remote_config], e.g. the
+    /// compression.
+    fn apply_remote_config(&mut self, _config: &ExporterConfig) {}
+
+    /// Box this transport to choose transports at runtime, see [crate::DynTransport].
+    fn boxed(self) -> crate::BoxTransport
+    where
+        Self: Sized + 'static,
+    {
+        Box::new(self)
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// Provides bearer tokens for the `authorization` header of [HttpPostTransport] requests.
+///
+/// The provider is asked for a token before every request. `refresh` is `true` if the server rejected the previous
+/// token with `401 Unauthorized`, in which case a new token should be obtained. The request is then retried once.
+///
+/// Implemented for closures `Fn(bool) -> impl Future<Output = io::Result<String>>`.
+pub trait AuthTokenProvider {
+    fn token(&self, refresh: bool) -> Pin<Box<dyn Future<Output = io::Result<String>> + '_>>;
+}
+
+impl<F, Fut> AuthTokenProvider for F
+where
+    F: Fn(bool) -> Fut,
+    Fut: Future<Output = io::Result<String>> + 'static,
+{
+    fn token(&self, refresh: bool) -> Pin<Box<dyn Future<Output = io::Result<String>> + '_>> {
+        Box::pin(self(refresh))
+    }
+}
+
+#[derive(Clone)]
+struct AuthToken(Rc<dyn AuthTokenProvider>);
+
+impl std::fmt::Debug for AuthToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("AuthToken").finish_non_exhaustive()
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// The [`priority`](https://developer.mozilla.org/en-US/docs/Web/API/RequestInit#priority) hint of a fetch request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FetchPriority {
+    High,
+    Low,
+    Auto,
+}
+
+impl FetchPriority {
+    fn as_str(self) -> &'static str {
+        match self {
+            FetchPriority::High => "high",
+            FetchPriority::Low => "low",
+            FetchPriority::Auto => "auto",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FetchOptions {
+    credentials: RequestCredentials,
+    mode: Option<RequestMode>,
+    referrer_policy: Option<ReferrerPolicy>,
+    keepalive: bool,
+    priority: Option<FetchPriority>,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            credentials: RequestCredentials::Include,
+            mode: None,
+            referrer_policy: None,
+            keepalive: false,
+            priority: None,
+        }
+    }
 }
 
 // -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
@@ -26,12 +162,23 @@ pub trait Transport {
 #[doc(hidden)]
 pub struct EndpointUndefined;
 #[doc(hidden)]
-pub struct EndpointDefined(String);
+pub struct EndpointDefined(pub(crate) String);
 
 #[derive(Default, Debug)]
 pub struct HttpPostTransport<T> {
+    content_type: &'static str,
     timeout: Duration,
     compression: Option<Compression>,
+    min_compression_size: usize,
+    adaptive_compression: Option<AdaptiveCompression>,
+    adaptive_level: Cell<Option<u32>>,
+    #[cfg(feature = "compression-worker")]
+    compression_worker: Option<Rc<crate::CompressionWorker>>,
+    headers: Vec<(String, String)>,
+    idempotency_key_header: bool,
+    auth_token: Option<AuthToken>,
+    fetch: FetchOptions,
+    remote_config: RefCell<Option<ExporterConfig>>,
     self_metrics: bool,
     endpoint: T,
 }
@@ -39,8 +186,19 @@ pub struct HttpPostTransport<T> {
 impl Default for HttpPostTransport<EndpointUndefined> {
     fn default() -> Self {
         Self {
+            content_type: PayloadVersion::default().content_type(),
             timeout: Duration::from_secs(5),
             compression: None,
+            min_compression_size: 0,
+            adaptive_compression: None,
+            adaptive_level: Cell::new(None),
+            #[cfg(feature = "compression-worker")]
+            compression_worker: None,
+            headers: Vec::new(),
+            idempotency_key_header: false,
+            auth_token: None,
+            fetch: FetchOptions::default(),
+            remote_config: RefCell::new(None),
             self_metrics: false,
             endpoint: EndpointUndefined,
         }
@@ -59,6 +217,85 @@ impl HttpPostTransport<EndpointUndefined> {
         self
     }
 
+    /// Send payloads smaller than `size` bytes uncompressed. Payloads that compression doesn't make smaller are
+    /// always sent uncompressed.
+    pub fn min_compression_size(mut self, size: usize) -> Self {
+        self.min_compression_size = size;
+        self
+    }
+
+    /// Lower the compression level when compressing takes too long on this device or hardly shrinks the payloads, see
+    /// [AdaptiveCompression]. The level set with [Self::compression] is the maximum.
+    pub fn adaptive_compression(mut self, adaptive_compression: Option<AdaptiveCompression>) -> Self {
+        self.adaptive_compression = adaptive_compression;
+        self
+    }
+
+    /// Compress payloads in a Web Worker instead of the main thread, see [crate::CompressionWorker]. Share the worker
+    /// with [crate::Asn1Encoder::worker] to encode there as well.
+    #[cfg(feature = "compression-worker")]
+    pub fn compression_worker(mut self, worker: Option<Rc<crate::CompressionWorker>>) -> Self {
+        self.compression_worker = worker;
+        self
+    }
+
+    /// Add a header that is sent with every request. `content-type` and `content-encoding` are set by the transport
+    /// itself.
+    pub fn header(mut self, name: impl ToString, value: impl ToString) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Send the [PayloadMetadata::idempotency_key] of every payload in the [IDEMPOTENCY_KEY_HEADER] header, so the
+    /// server can drop duplicates before decoding. Disabled by default: the header is not CORS-safelisted, cross-origin
+    /// endpoints must allow it with `Access-Control-Allow-Headers` or the browser rejects the requests. Version 1
+    /// payloads carry the metadata either way, see [metrics_exporter_wasm_core::DecodedPayload::sequence].
+    pub fn idempotency_key_header(mut self, enabled: bool) -> Self {
+        self.idempotency_key_header = enabled;
+        self
+    }
+
+    /// Set a provider for bearer tokens that are sent in the `authorization` header. See [AuthTokenProvider].
+    pub fn auth_token_provider(mut self, provider: impl AuthTokenProvider + 'static) -> Self {
+        self.auth_token = Some(AuthToken(Rc::new(provider)));
+        self
+    }
+
+    /// Set the `credentials` mode of the fetch requests. Defaults to [RequestCredentials::Include]. Use
+    /// [RequestCredentials::Omit] or [RequestCredentials::SameOrigin] for third-party collectors.
+    pub fn credentials(mut self, credentials: RequestCredentials) -> Self {
+        self.fetch.credentials = credentials;
+        self
+    }
+
+    /// Set the `mode` of the fetch requests, e.g. [RequestMode::Cors]. Uses the browser default if not set.
+    pub fn mode(mut self, mode: RequestMode) -> Self {
+        self.fetch.mode = Some(mode);
+        self
+    }
+
+    /// Set the `referrerPolicy` of the fetch requests. Uses the browser default if not set.
+    pub fn referrer_policy(mut self, referrer_policy: ReferrerPolicy) -> Self {
+        self.fetch.referrer_policy = Some(referrer_policy);
+        self
+    }
+
+    /// Set `keepalive` on the fetch requests so that they outlive the page, e.g. for sends during `pagehide`.
+    ///
+    /// Browsers limit the total body size of in-flight keepalive requests to 64 KiB, larger requests fail. Combine
+    /// with [crate::MetricsHttpSender::max_payload_size] to stay below that limit.
+    pub fn keepalive(mut self, keepalive: bool) -> Self {
+        self.fetch.keepalive = keepalive;
+        self
+    }
+
+    /// Set the `priority` hint of the fetch requests. [FetchPriority::Low] keeps metrics from competing with more
+    /// important requests. Ignored by browsers that don't support it.
+    pub fn priority(mut self, priority: FetchPriority) -> Self {
+        self.fetch.priority = Some(priority);
+        self
+    }
+
     /// Set whether to emit internal metrics.
     pub fn self_metrics(mut self, self_metrics: bool) -> Self {
         self.self_metrics = self_metrics;
@@ -74,67 +311,218 @@ impl HttpPostTransport<EndpointUndefined> {
     /// Set the endpoint for the metrics transport.
     pub fn endpoint(self, endpoint: impl ToString) -> HttpPostTransport<EndpointDefined> {
         HttpPostTransport {
+            content_type: self.content_type,
             timeout: self.timeout,
             compression: self.compression,
+            min_compression_size: self.min_compression_size,
+            adaptive_compression: self.adaptive_compression,
+            adaptive_level: self.adaptive_level,
+            #[cfg(feature = "compression-worker")]
+            compression_worker: self.compression_worker,
+            headers: self.headers,
+            idempotency_key_header: self.idempotency_key_header,
+            auth_token: self.auth_token,
+            fetch: self.fetch,
+            remote_config: self.remote_config,
             self_metrics: self.self_metrics,
             endpoint: EndpointDefined(endpoint.to_string()),
         }
     }
 }
 
-impl Transport for HttpPostTransport<EndpointDefined> {
-    fn enable_self_metrics(&mut self, self_metrics: bool) {
-        self.self_metrics = self_metrics;
+/// The `content-encoding` of a compressed body and the dictionary it was compressed with.
+#[derive(Debug, Clone, Copy)]
+struct ContentEncoding {
+    name: &'static str,
+    dictionary_id: Option<u32>,
+}
+
+impl HttpPostTransport<EndpointDefined> {
+    /// Returns the content encoding and the body to send.
+    async fn compress(&self, payload: Bytes) -> Result<(Option<ContentEncoding>, Bytes), TransportError> {
+        if payload.len() < self.min_compression_size {
+            return Ok((None, payload));
+        }
+        let Some((configured, compression)) = self.compression.map(|configured| {
+            let level = self.adaptive_level.get();
+            (
+                configured,
+                level.map_or(configured, |level| configured.with_level(level)),
+            )
+        }) else {
+            return Ok((None, payload));
+        };
+
+        #[cfg(feature = "compression-worker")]
+        let compressed_in_worker = match &self.compression_worker {
+            Some(worker) => worker.compress(compression, &payload).await,
+            None => None,
+        };
+        #[cfg(not(feature = "compression-worker"))]
+        let compressed_in_worker = None;
+        // Only the main thread is timed, compressing in the worker doesn't block the page.
+        let (content_encoding, body, elapsed) = match compressed_in_worker {
+            Some((content_encoding, body)) => (content_encoding, body, Duration::ZERO),
+            None => {
+                let start = Instant::now();
+                let (content_encoding, body) =
+                    compression.compress(&payload).await.map_err(TransportError::Compress)?;
+                (content_encoding, body, start.elapsed())
+            }
+        };
+
+        if let (Some(adaptive), Some(level), Some(max_level)) =
+            (self.adaptive_compression, compression.level(), configured.level())
+        {
+            let ratio = body.len() as f64 / payload.len().max(1) as f64;
+            let next = adaptive.next_level(level, max_level, elapsed, ratio);
+            if next != level {
+                debug!(%level, %next, ?elapsed, %ratio, "adjusting compression level");
+            }
+            self.adaptive_level.set(Some(next));
+        }
+
+        if body.len() >= payload.len() {
+            return Ok((None, payload));
+        }
+        Ok((
+            Some(ContentEncoding {
+                name: content_encoding,
+                dictionary_id: compression.dictionary_id(),
+            }),
+            body,
+        ))
     }
 
-    fn send(&self, payload: &Bytes) -> impl Future<Output = io::Result<()>> {
-        let timeout = self.timeout;
+    async fn post(
+        &self,
+        body: &Bytes,
+        content_encoding: Option<ContentEncoding>,
+        idempotency_key: Option<&str>,
+        token: Option<&str>,
+        signal: &AbortSignal,
+    ) -> Result<Response, TransportError> {
         let EndpointDefined(endpoint) = &self.endpoint;
-        let compression = self.compression;
+
+        let headers = Headers::new();
+        for (name, value) in &self.headers {
+            headers.set(name, value);
+        }
+        headers.set("content-type", self.content_type);
+        if let Some(ContentEncoding { name, dictionary_id }) = content_encoding {
+            headers.set("content-encoding", name);
+            if let Some(dictionary_id) = dictionary_id {
+                headers.set(ZSTD_DICTIONARY_ID_HEADER, &dictionary_id.to_string());
+            }
+        }
+        if let Some(idempotency_key) = idempotency_key {
+            headers.set(IDEMPOTENCY_KEY_HEADER, idempotency_key);
+        }
+        if let Some(token) = token {
+            headers.set("authorization", &format!("Bearer {token}"));
+        }
+
+        // gloo's RequestBuilder doesn't know about keepalive and priority, so the RequestInit is built by hand.
+        let FetchOptions {
+            credentials,
+            mode,
+            referrer_policy,
+            keepalive,
+            priority,
+        } = self.fetch;
+        let init = RequestInit::new();
+        init.set_method("POST");
+        init.set_headers(&headers.into_raw());
+        init.set_credentials(credentials);
+        if let Some(mode) = mode {
+            init.set_mode(mode);
+        }
+        if let Some(referrer_policy) = referrer_policy {
+            init.set_referrer_policy(referrer_policy);
+        }
+        if keepalive {
+            Reflect::set(&init, &"keepalive".into(), &true.into()).map_err(js_err)?;
+        }
+        if let Some(priority) = priority {
+            Reflect::set(&init, &"priority".into(), &priority.as_str().into()).map_err(js_err)?;
+        }
+        init.set_signal(Some(signal));
+        init.set_body(&Uint8Array::from(&body[..]));
+
+        let request = web_sys::Request::new_with_str_and_init(endpoint, &init).map_err(js_err)?;
+        Request::from(request).send().await.map_err(|err| match err {
+            gloo::net::Error::JsError(err) if err.name == "AbortError" => TransportError::Aborted,
+            err => TransportError::network(err),
+        })
+    }
+
+    fn send_with_idempotency_key(
+        &self,
+        payload: &Bytes,
+        idempotency_key: Option<String>,
+    ) -> impl Future<Output = Result<(), TransportError>> + '_ {
+        let timeout = self.timeout;
         let self_metrics = self.self_metrics;
 
         let controller = AbortController::new().unwrap();
         let signal = controller.signal();
 
-        let headers = Headers::new();
-        headers.set("content-type", "application/octet-stream");
-
-        let body = match compression {
-            #[cfg(feature = "compress-zstd-external")]
-            Some(Compression::Zstd { level }) => {
-                headers.set("content-encoding", "zstd");
-                Compression::compress_zstd_external(payload, level)
-            }
-            #[cfg(feature = "compress-brotli")]
-            Some(Compression::Brotli) => {
-                headers.set("content-encoding", "br");
-                Compression::compress_br(payload)
-            }
-            None => io::Result::Ok(payload.clone()),
-        };
-
-        let req = RequestBuilder::new(endpoint.as_str())
-            .method(Method::POST)
-            .headers(headers)
-            .abort_signal(Some(&signal))
-            .credentials(RequestCredentials::Include);
+        let payload = payload.clone();
 
         async move {
-            let body = body?;
+            let (content_encoding, body) = self.compress(payload).await?;
             let body_size = body.len();
-            let req = req.body(body.to_vec()).map_err(err)?;
+            let idempotency_key = idempotency_key.as_deref();
 
             let fut = async {
-                let res = req.send().await.map_err(err)?;
+                let res = match &self.auth_token {
+                    None => {
+                        self.post(&body, content_encoding, idempotency_key, None, &signal)
+                            .await?
+                    }
+                    Some(AuthToken(provider)) => {
+                        let token = provider.token(false).await.map_err(TransportError::network)?;
+                        let res = self
+                            .post(&body, content_encoding, idempotency_key, Some(&token), &signal)
+                            .await?;
+                        if res.status() == 401 {
+                            debug!("metrics endpoint rejected auth token, refreshing");
+                            let token = provider.token(true).await.map_err(TransportError::network)?;
+                            self.post(&body, content_encoding, idempotency_key, Some(&token), &signal)
+                                .await?
+                        } else {
+                            res
+                        }
+                    }
+                };
+
                 if !res.ok() {
-                    let text = res.text().await.map_err(|err| err.to_string()).unwrap_or_default();
                     let status = res.status();
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("Failed to fetch server info. status={status} {text}"),
-                    ));
+                    let retry_after = res.headers().get("retry-after").and_then(|v| parse_retry_after(&v));
+                    let body = res.text().await.map_err(|err| err.to_string()).unwrap_or_default();
+                    return Err(TransportError::Http(HttpError {
+                        status,
+                        retry_after,
+                        body,
+                    }));
                 };
 
+                let is_config = res
+                    .headers()
+                    .get("content-type")
+                    .is_some_and(|content_type| content_type == EXPORTER_CONFIG_CONTENT_TYPE);
+                if is_config {
+                    match res
+                        .binary()
+                        .await
+                        .map_err(io::Error::other)
+                        .and_then(|body| ExporterConfig::decode(&body))
+                    {
+                        Ok(config) => *self.remote_config.borrow_mut() = Some(config),
+                        Err(err) => warn!(?err, "failed to decode exporter config from metrics endpoint"),
+                    }
+                }
+
                 if self_metrics {
                     metrics::histogram!("metrics_exporter_compressed_payload_size").record(body_size as f64);
                 }
@@ -147,13 +535,71 @@ impl Transport for HttpPostTransport<EndpointDefined> {
                 res = fut => res,
                 _ = wasmtimer::tokio::sleep(timeout) => {
                     controller.abort();
-                    Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Timed out"))
+                    Err(TransportError::Timeout)
                 }
             }
         }
     }
 }
 
-fn err(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
-    io::Error::new(io::ErrorKind::Other, err)
+impl Transport for HttpPostTransport<EndpointDefined> {
+    fn enable_self_metrics(&mut self, self_metrics: bool) {
+        self.self_metrics = self_metrics;
+    }
+
+    fn set_content_type(&mut self, content_type: &'static str) {
+        self.content_type = content_type;
+    }
+
+    fn take_remote_config(&self) -> Option<ExporterConfig> {
+        self.remote_config.take()
+    }
+
+    fn apply_remote_config(&mut self, config: &ExporterConfig) {
+        let Some(compression) = config.compression else {
+            return;
+        };
+        self.adaptive_level.set(None);
+        self.compression = match compression {
+            PayloadCompression::None => None,
+            #[cfg(feature = "compress-brotli")]
+            PayloadCompression::Brotli => match self.compression {
+                Some(Compression::Brotli { quality, window }) => Some(Compression::Brotli { quality, window }),
+                _ => Some(Compression::Brotli { quality: 5, window: 22 }),
+            },
+            // Without `compress-zstd` this requires zstd to be initialized, see [crate::zstd_external::initialize].
+            #[cfg(any(feature = "compress-zstd", feature = "compress-zstd-external"))]
+            PayloadCompression::Zstd => match self.compression {
+                Some(compression @ Compression::Zstd { .. }) => Some(compression),
+                #[cfg(feature = "compress-zstd-external")]
+                Some(compression @ Compression::ZstdWithDictionary { .. }) => Some(compression),
+                _ => Some(Compression::Zstd { level: 3 }),
+            },
+            #[allow(unreachable_patterns)]
+            compression => {
+                warn!(
+                    ?compression,
+                    "compression requested by the metrics endpoint is not enabled"
+                );
+                return;
+            }
+        };
+    }
+
+    fn send(&self, payload: &Bytes) -> impl Future<Output = Result<(), TransportError>> {
+        self.send_with_idempotency_key(payload, None)
+    }
+
+    fn send_with_metadata(
+        &self,
+        payload: &Bytes,
+        metadata: &PayloadMetadata,
+    ) -> impl Future<Output = Result<(), TransportError>> {
+        let idempotency_key = self.idempotency_key_header.then(|| metadata.idempotency_key());
+        self.send_with_idempotency_key(payload, idempotency_key)
+    }
+}
+
+fn js_err(err: JsValue) -> TransportError {
+    TransportError::Network(format!("{err:?}"))
 }
@@ -48,38 +48,107 @@ For how to use compression and how to implement a receiving server handler see t
 */
 
 mod compression;
+#[cfg(feature = "compression-worker")]
+mod compression_worker;
+mod console_transport;
+mod dyn_transport;
+mod encoder;
 mod http_transport;
+#[cfg(any(test, feature = "testing"))]
+mod memory_transport;
 mod metrics_http_sender;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+mod priority;
 mod recorder;
+mod transport_combinators;
+mod transport_error;
+mod websocket_transport;
 
 #[cfg(feature = "compress-zstd-external")]
 pub mod zstd_external;
 
-pub use compression::Compression;
+#[cfg(feature = "compress-zstd-external")]
+pub use compression::ZstdDictionary;
+pub use compression::{
+    AdaptiveCompression,
+    Compression,
+};
+#[cfg(feature = "compression-worker")]
+pub use compression_worker::{
+    compression_worker_main,
+    CompressionWorker,
+    DEFAULT_WORKER_TIMEOUT,
+};
+pub use console_transport::ConsoleTransport;
+pub use dyn_transport::{
+    BoxTransport,
+    DynTransport,
+};
+#[cfg(feature = "serde")]
+pub use encoder::JsonEncoder;
+pub use encoder::{
+    Asn1Encoder,
+    Encoder,
+};
 pub use http_transport::{
+    AuthTokenProvider,
     EndpointDefined,
     EndpointUndefined,
+    FetchPriority,
     HttpPostTransport,
     Transport,
 };
+#[cfg(any(test, feature = "testing"))]
+pub use memory_transport::MemoryTransport;
 pub use metrics_exporter_wasm_core::{
     Asn1Decode,
     Asn1Encode,
+    ClientSession,
+    DecodeLimits,
     Event,
     Events,
+    ExporterConfig,
     MetricOperation,
     MetricType,
+    PayloadCompression,
+    PayloadVersion,
     RecordedEvent,
     RecordedEvents,
+    SamplingRule,
+    EXPORTER_CONFIG_CONTENT_TYPE,
+    PAYLOAD_CONTENT_TYPE_V0,
+    PAYLOAD_CONTENT_TYPE_V1,
+    WEBSOCKET_ACK,
+    ZSTD_DICTIONARY_ID_HEADER,
 };
 pub use metrics_http_sender::{
     Batch,
+    ChunkOverflow,
     MetricsHttpSender,
 };
+pub use priority::{
+    Priority,
+    PriorityRules,
+};
 pub use recorder::{
     WasmRecorder,
     WasmRecorderBuilder,
 };
+pub use transport_combinators::{
+    FailoverTransport,
+    TeeTransport,
+};
+pub use transport_error::{
+    HttpError,
+    TransportError,
+};
+pub use web_sys::{
+    ReferrerPolicy,
+    RequestCredentials,
+    RequestMode,
+};
+pub use websocket_transport::WebSocketTransport;
 
 #[macro_use]
 extern crate tracing;
@@ -0,0 +1,243 @@
+use crate::{
+    DecodeLimits,
+    Event,
+    MetricOperation,
+    PayloadVersion,
+    RecordedEvent,
+    RecordedEvents,
+    Transport,
+    TransportError,
+};
+use bytes::Bytes;
+use std::{
+    cell::RefCell,
+    io,
+    rc::Rc,
+    time::Duration,
+};
+
+#[derive(Debug, Default)]
+struct State {
+    payloads: Vec<Bytes>,
+    attempts: usize,
+    fail_next: usize,
+    delay: Option<Duration>,
+    version: PayloadVersion,
+}
+
+/// A [Transport] that keeps every payload in memory. Meant for tests of code that uses the
+/// [crate::MetricsHttpSender].
+///
+/// The transport is a cheap handle: keep a clone to inspect the payloads after handing the transport to the sender.
+/// Payloads are decoded as [RecordedEvents] in the [PayloadVersion] of the encoder's content type, so this only works
+/// with the [crate::Asn1Encoder].
+///
+/// ```ignore
+/// let transport = MemoryTransport::new();
+/// let _guard = MetricsHttpSender::new(transport.clone()).start_with_metrics_recorder(&recorder);
+/// // ... run the instrumented code and wait for a send ...
+/// transport.assert_counter_incremented("requests", 3);
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct MemoryTransport {
+    state: Rc<RefCell<State>>,
+}
+
+impl MemoryTransport {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Let the next `n` sends fail with an error, e.g. to test the retry logic.
+    pub fn fail_next(&self, n: usize) {
+        self.state.borrow_mut().fail_next = n;
+    }
+
+    /// Delay every send by `delay` before it completes or fails.
+    pub fn delay(&self, delay: Option<Duration>) {
+        self.state.borrow_mut().delay = delay;
+    }
+
+    /// How often [Transport::send] was called, including failed attempts.
+    pub fn attempts(&self) -> usize {
+        self.state.borrow().attempts
+    }
+
+    /// The payloads that were sent successfully.
+    pub fn payloads(&self) -> Vec<Bytes> {
+        self.state.borrow().payloads.clone()
+    }
+
+    /// The successfully sent payloads decoded as [RecordedEvents].
+    pub fn recorded_events(&self) -> io::Result<Vec<RecordedEvents>> {
+        let state = self.state.borrow();
+        state
+            .payloads
+            .iter()
+            .map(|payload| state.version.decode(payload, &DecodeLimits::UNLIMITED))
+            .collect()
+    }
+
+    /// All events of all successfully sent payloads, in order.
+    ///
+    /// # Panics
+    ///
+    /// If a payload can't be decoded.
+    pub fn events(&self) -> Vec<RecordedEvent> {
+        self.recorded_events()
+            .expect("failed to decode payload")
+            .into_iter()
+            .flat_map(|events| Vec::<RecordedEvent>::try_from(events).expect("failed to decode payload"))
+            .collect()
+    }
+
+    /// Remove all recorded payloads.
+    pub fn clear(&self) {
+        self.state.borrow_mut().payloads.clear();
+    }
+
+    /// The operations recorded for the metric `name`, in order.
+    pub fn operations(&self, name: &str) -> Vec<MetricOperation> {
+        self.events()
+            .into_iter()
+            .filter_map(|event| match event.event {
+                Event::Metric { key, op } if key.name() == name => Some(op),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The sum of all increments of the counter `name`.
+    pub fn counter_increments(&self, name: &str) -> u64 {
+        self.operations(name)
+            .into_iter()
+            .map(|op| match op {
+                MetricOperation::IncrementCounter(value) => value,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// The value of the gauge `name` after applying all recorded operations, `None` if it was never recorded.
+    pub fn gauge_value(&self, name: &str) -> Option<f64> {
+        self.operations(name).into_iter().fold(None, |value, op| match op {
+            MetricOperation::SetGauge(v) => Some(v),
+            MetricOperation::IncrementGauge(v) => Some(value.unwrap_or_default() + v),
+            MetricOperation::DecrementGauge(v) => Some(value.unwrap_or_default() - v),
+            _ => value,
+        })
+    }
+
+    /// All values recorded for the histogram `name`.
+    pub fn histogram_values(&self, name: &str) -> Vec<f64> {
+        self.operations(name)
+            .into_iter()
+            .filter_map(|op| match op {
+                MetricOperation::RecordHistogram(value) => Some(value),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[track_caller]
+    pub fn assert_counter_incremented(&self, name: &str, by: u64) {
+        let increments = self.counter_increments(name);
+        assert_eq!(
+            increments, by,
+            "counter {name} was incremented by {increments}, expected {by}"
+        );
+    }
+
+    #[track_caller]
+    pub fn assert_gauge(&self, name: &str, expected: f64) {
+        let value = self.gauge_value(name);
+        assert_eq!(value, Some(expected), "gauge {name} is {value:?}, expected {expected}");
+    }
+
+    #[track_caller]
+    pub fn assert_no_metric(&self, name: &str) {
+        let operations = self.operations(name);
+        assert!(operations.is_empty(), "expected no {name} metric, got {operations:?}");
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn set_content_type(&mut self, content_type: &'static str) {
+        if let Ok(version) = PayloadVersion::from_content_type(Some(content_type)) {
+            self.state.borrow_mut().version = version;
+        }
+    }
+
+    async fn send(&self, payload: &Bytes) -> Result<(), TransportError> {
+        let delay = {
+            let mut state = self.state.borrow_mut();
+            state.attempts += 1;
+            state.delay
+        };
+        if let Some(delay) = delay {
+            wasmtimer::tokio::sleep(delay).await;
+        }
+
+        let mut state = self.state.borrow_mut();
+        if state.fail_next > 0 {
+            state.fail_next -= 1;
+            return Err(TransportError::network("MemoryTransport: scripted failure"));
+        }
+        state.payloads.push(payload.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemoryTransport;
+    use crate::{
+        Event,
+        MetricOperation,
+        PayloadVersion,
+        RecordedEvent,
+        RecordedEvents,
+        Transport as _,
+    };
+    use bytes::Bytes;
+    use metrics::Key;
+
+    fn payload(ops: &[(&str, MetricOperation)]) -> Bytes {
+        let events = ops
+            .iter()
+            .map(|(name, op)| {
+                RecordedEvent::from(Event::Metric {
+                    key: Key::from_name(name.to_string()),
+                    op: *op,
+                })
+            })
+            .collect();
+        let events = RecordedEvents::new(metrics_exporter_wasm_core::util_time::now(), events);
+        Bytes::from(PayloadVersion::default().encode(&events).unwrap())
+    }
+
+    #[test]
+    fn records_payloads_and_scripted_failures() {
+        let transport = MemoryTransport::new();
+        transport.fail_next(1);
+
+        let first = payload(&[("requests", MetricOperation::IncrementCounter(1))]);
+        let second = payload(&[
+            ("requests", MetricOperation::IncrementCounter(2)),
+            ("queue", MetricOperation::SetGauge(4.0)),
+            ("queue", MetricOperation::DecrementGauge(1.5)),
+        ]);
+
+        futures::executor::block_on(async {
+            assert!(transport.send(&first).await.is_err());
+            transport.send(&first).await.unwrap();
+            transport.send(&second).await.unwrap();
+        });
+
+        assert_eq!(transport.attempts(), 3);
+        assert_eq!(transport.payloads().len(), 2);
+        transport.assert_counter_incremented("requests", 3);
+        transport.assert_gauge("queue", 2.5);
+        transport.assert_no_metric("latency");
+    }
+}
@@ -1,6 +1,11 @@
 use crate::{
+    Asn1Encoder,
+    Encoder,
     Event,
+    Priority,
+    PriorityRules,
     Transport,
+    TransportError,
     WasmRecorder,
 };
 use backon::{
@@ -14,12 +19,19 @@ use futures::{
 };
 use metrics_exporter_wasm_core::{
     util_time,
-    Asn1Encode,
+    ClientSession,
+    ExporterConfig,
+    PayloadMetadata,
     RecordedEvent,
     RecordedEvents,
 };
 use std::{
-    collections::VecDeque,
+    cell::Cell,
+    collections::{
+        HashSet,
+        VecDeque,
+    },
+    rc::Rc,
     time::Duration,
 };
 use tokio::sync::broadcast;
@@ -35,21 +47,88 @@ use wasmtimer::{
 /// A generic batch to represent the data that gets accumulated and then sent using the [MetricsHttpSender].
 pub trait Batch {
     type Item: Clone + Send + 'static;
-    type CompletedBatch: Asn1Encode;
+    /// The batch that gets encoded by the [Encoder] of the [MetricsHttpSender].
+    type CompletedBatch;
 
     fn new() -> Self;
     fn pop_front(&mut self) -> Option<Self::Item>;
     fn push_back(&mut self, item: Self::Item);
+
+    /// Add an item with the given [Priority]. The default implementation ignores the priority.
+    fn push_back_with_priority(&mut self, item: Self::Item, priority: Priority) {
+        let _ = priority;
+        self.push_back(item);
+    }
+
+    /// Evict the oldest item of the given `priority` to make room for a new item. Returns `None` if there is none.
+    ///
+    /// The sender evicts [Priority::Low] items first and [Priority::Normal] items only when there is no low priority
+    /// one. The default implementation ignores priorities and removes the oldest item.
+    fn evict(&mut self, priority: Priority) -> Option<Self::Item> {
+        let _ = priority;
+        self.pop_front()
+    }
+
+    /// The number of items of the given `priority`.
+    ///
+    /// The default implementation ignores priorities and reports all items as [Priority::Normal].
+    fn len_with_priority(&self, priority: Priority) -> usize {
+        if priority == Priority::Normal {
+            self.len()
+        } else {
+            0
+        }
+    }
+
+    /// The metric name of an item. Used to apply the sampling rules and disabled metrics of an [ExporterConfig] sent by
+    /// the server, items without a name are never filtered.
+    fn metric_name(item: &Self::Item) -> Option<&str> {
+        let _ = item;
+        None
+    }
+
     fn len(&self) -> usize;
     fn is_empty(&self) -> bool {
         self.len() == 0
     }
     fn finalize(&mut self) -> Self::CompletedBatch;
+
+    /// Finalize the batch into several completed batches, in order, each holding at most `max_items` items.
+    ///
+    /// The default implementation does not split and returns a single batch.
+    fn finalize_chunks(&mut self, max_items: usize) -> Vec<Self::CompletedBatch> {
+        let _ = max_items;
+        vec![self.finalize()]
+    }
+
+    /// Split a completed batch into two, preserving order. Returns the batch unchanged if it can't be split any
+    /// further.
+    ///
+    /// The default implementation never splits.
+    fn split_completed(
+        batch: Self::CompletedBatch,
+    ) -> Result<(Self::CompletedBatch, Self::CompletedBatch), Self::CompletedBatch> {
+        Err(batch)
+    }
+}
+
+/// What to do when a batch grows beyond [MetricsHttpSender::max_chunk_size].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkOverflow {
+    /// Drop the oldest items to make room for new ones. [Priority::Low] items are dropped first, [Priority::Normal]
+    /// items only when there is no low priority one and [Priority::Critical] items never, see [Priority].
+    #[default]
+    DropOldest,
+    /// Keep all items and split the batch into multiple payloads of at most `max_chunk_size` items each. The payloads
+    /// are sent in order.
+    Split,
 }
 
 struct BatchedEvents {
     batch_start_time: chrono::DateTime<chrono::Utc>,
-    events: VecDeque<RecordedEvent>,
+    events: VecDeque<(Priority, RecordedEvent)>,
+    /// The number of events per [Priority], indexed by `Priority as usize`.
+    counts: [usize; 3],
 }
 
 impl Batch for BatchedEvents {
@@ -61,15 +140,43 @@ impl Batch for BatchedEvents {
         Self {
             batch_start_time: util_time::now(),
             events: Default::default(),
+            counts: Default::default(),
         }
     }
 
     fn pop_front(&mut self) -> Option<Self::Item> {
-        self.events.pop_front().map(Into::into)
+        let (priority, event) = self.events.pop_front()?;
+        self.counts[priority as usize] -= 1;
+        Some(event.into())
     }
 
     fn push_back(&mut self, item: Self::Item) {
-        self.events.push_back(RecordedEvent::from(item));
+        self.push_back_with_priority(item, Priority::default());
+    }
+
+    fn push_back_with_priority(&mut self, item: Self::Item, priority: Priority) {
+        self.counts[priority as usize] += 1;
+        self.events.push_back((priority, RecordedEvent::from(item)));
+    }
+
+    fn evict(&mut self, priority: Priority) -> Option<Self::Item> {
+        if self.counts[priority as usize] == 0 {
+            return None;
+        }
+        let index = self.events.iter().position(|(p, _)| *p == priority)?;
+        self.counts[priority as usize] -= 1;
+        self.events.remove(index).map(|(_, event)| event.into())
+    }
+
+    fn len_with_priority(&self, priority: Priority) -> usize {
+        self.counts[priority as usize]
+    }
+
+    fn metric_name(item: &Self::Item) -> Option<&str> {
+        match item {
+            Event::Description { .. } => None,
+            Event::Metric { key, .. } => Some(key.name()),
+        }
     }
 
     fn len(&self) -> usize {
@@ -78,38 +185,137 @@ impl Batch for BatchedEvents {
 
     fn finalize(&mut self) -> Self::CompletedBatch {
         let start_time = self.batch_start_time;
-        let events = self.events.drain(..).collect();
+        let events = self.events.drain(..).map(|(_, event)| event).collect();
+        self.counts = Default::default();
         self.batch_start_time = util_time::now();
         RecordedEvents::new(start_time, events)
     }
+
+    fn finalize_chunks(&mut self, max_items: usize) -> Vec<Self::CompletedBatch> {
+        let max_items = max_items.max(1);
+        let mut batch = self.finalize();
+        let mut chunks = Vec::with_capacity(batch.len().div_ceil(max_items));
+        while batch.len() > max_items {
+            let rest = batch.split_off(max_items);
+            chunks.push(batch);
+            batch = rest;
+        }
+        chunks.push(batch);
+        chunks
+    }
+
+    fn split_completed(
+        mut batch: Self::CompletedBatch,
+    ) -> Result<(Self::CompletedBatch, Self::CompletedBatch), Self::CompletedBatch> {
+        if batch.len() < 2 {
+            return Err(batch);
+        }
+        let rest = batch.split_off(batch.len() / 2);
+        Ok((batch, rest))
+    }
 }
 
+type ErrorCallback = Rc<dyn Fn(&TransportError)>;
+
+/// Bounds for the send frequency of an [ExporterConfig] sent by the server, so that a bad config can neither busy loop
+/// the sender nor stop it.
+const MIN_REMOTE_SEND_FREQUENCY: Duration = Duration::from_secs(1);
+const MAX_REMOTE_SEND_FREQUENCY: Duration = Duration::from_secs(10 * 60);
+
 /// A metrics exporter for a [WasmRecorder].
 ///
-/// The payload that gets send is actually generic, see the [Batch] trait and [Self::start_with_receiver] method.
-pub struct MetricsHttpSender<T> {
+/// The payload that gets send is actually generic, see the [Batch] trait and [Self::start_with_receiver] method. How it
+/// gets encoded is decided by the [Encoder], ASN.1 by default.
+pub struct MetricsHttpSender<T, E = Asn1Encoder> {
     max_chunk_size: Option<usize>,
+    max_critical_events: Option<usize>,
+    chunk_overflow: ChunkOverflow,
+    max_payload_size: Option<usize>,
+    priority_rules: PriorityRules,
     send_frequency: Duration,
     self_metrics: bool,
+    on_error: Option<ErrorCallback>,
     transport: T,
+    encoder: E,
+    session: Option<ClientSession>,
 }
 
 impl<T> MetricsHttpSender<T> {
     pub fn new(transport: T) -> Self {
         Self {
             max_chunk_size: None,
+            max_critical_events: None,
+            chunk_overflow: ChunkOverflow::default(),
+            max_payload_size: None,
+            priority_rules: PriorityRules::default(),
             send_frequency: Duration::from_secs(15),
             self_metrics: false,
+            on_error: None,
             transport,
+            encoder: Asn1Encoder::default(),
+            session: None,
+        }
+    }
+}
+
+impl<T, E> MetricsHttpSender<T, E> {
+    /// Set the [Encoder] for the payloads. The content type of the encoder is passed on to the transport with
+    /// [Transport::set_content_type].
+    pub fn encoder<E2>(self, encoder: E2) -> MetricsHttpSender<T, E2> {
+        MetricsHttpSender {
+            max_chunk_size: self.max_chunk_size,
+            max_critical_events: self.max_critical_events,
+            chunk_overflow: self.chunk_overflow,
+            max_payload_size: self.max_payload_size,
+            priority_rules: self.priority_rules,
+            send_frequency: self.send_frequency,
+            self_metrics: self.self_metrics,
+            on_error: self.on_error,
+            transport: self.transport,
+            encoder,
+            session: self.session,
         }
     }
 
     /// How many metrics events to maximally send in one request.
+    ///
+    /// What happens when more events arrive is controlled by [Self::chunk_overflow]. With [ChunkOverflow::DropOldest],
+    /// [Priority::Critical] events don't count towards this limit, see [Self::max_critical_events].
     pub fn max_chunk_size(mut self, size: Option<usize>) -> Self {
         self.max_chunk_size = size;
         self
     }
 
+    /// How many [Priority::Critical] events a batch holds at most with [ChunkOverflow::DropOldest], in addition to
+    /// [Self::max_chunk_size] other events. Defaults to [Self::max_chunk_size]. Critical events are never evicted, new
+    /// ones are dropped once the limit is reached.
+    pub fn max_critical_events(mut self, size: Option<usize>) -> Self {
+        self.max_critical_events = size;
+        self
+    }
+
+    /// Set what to do when a batch exceeds [Self::max_chunk_size]. Defaults to [ChunkOverflow::DropOldest].
+    pub fn chunk_overflow(mut self, chunk_overflow: ChunkOverflow) -> Self {
+        self.chunk_overflow = chunk_overflow;
+        self
+    }
+
+    /// The maximum size of an encoded payload in bytes. Larger payloads are split in halves until they fit (as far as
+    /// [Batch::split_completed] allows). Only used with [ChunkOverflow::Split].
+    ///
+    /// Independent of this setting, payloads the server rejected with `413 Payload Too Large` are split and later
+    /// payloads are kept below the rejected size.
+    pub fn max_payload_size(mut self, size: Option<usize>) -> Self {
+        self.max_payload_size = size;
+        self
+    }
+
+    /// Set the rules that decide which metrics events get evicted first when a batch is full. See [PriorityRules].
+    pub fn priority_rules(mut self, priority_rules: PriorityRules) -> Self {
+        self.priority_rules = priority_rules;
+        self
+    }
+
     /// Set the frequency at which metrics are sent to the transport.
     pub fn send_frequency(mut self, frequency: Duration) -> Self {
         self.send_frequency = frequency;
@@ -124,14 +330,39 @@ impl<T> MetricsHttpSender<T> {
         self.self_metrics = self_metrics;
         self
     }
+
+    /// Describe the client in every payload, e.g. with a session id and the app version. Requires an [Encoder] that
+    /// supports it like the [Asn1Encoder] with [metrics_exporter_wasm_core::PayloadVersion::V1], see
+    /// [Encoder::set_session].
+    ///
+    /// Payloads are numbered per start of the sender, the numbers come with the session id and a random nonce (see
+    /// [Encoder::set_sequence] and [Transport::send_with_metadata]). Without a [ClientSession::session_id], a random
+    /// one is generated when the sender starts.
+    pub fn session(mut self, session: ClientSession) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// Called whenever a payload is lost: when encoding fails or when sending fails and retrying gave up or doesn't
+    /// help.
+    pub fn on_error(mut self, on_error: impl Fn(&TransportError) + 'static) -> Self {
+        self.on_error = Some(Rc::new(on_error));
+        self
+    }
 }
 
-impl<T: Transport + Send + 'static> MetricsHttpSender<T> {
+impl<T: Transport + 'static, E: 'static> MetricsHttpSender<T, E> {
     /// Start sending metrics to the endpoint specified.
     ///
     /// Returns a guard that will stop the transport when dropped.
-    pub fn start_with_metrics_recorder(self, recorder: &WasmRecorder) -> DropGuard {
-        self.start_with_receiver::<BatchedEvents>(recorder.subscribe(), None::<fn(&Event) -> bool>)
+    pub fn start_with_metrics_recorder(self, recorder: &WasmRecorder) -> DropGuard
+    where
+        E: Encoder<RecordedEvents>,
+    {
+        let priority_rules = self.priority_rules.clone();
+        self.start::<BatchedEvents>(recorder.subscribe(), None::<fn(&Event) -> bool>, move |event| {
+            priority_rules.priority(event)
+        })
     }
 
     /// Start sending metrics to the endpoint specified filtering out specific events.
@@ -141,17 +372,40 @@ impl<T: Transport + Send + 'static> MetricsHttpSender<T> {
         self,
         recorder: &WasmRecorder,
         filter_fn: impl Fn(&Event) -> bool + Copy + 'static,
-    ) -> DropGuard {
-        self.start_with_receiver::<BatchedEvents>(recorder.subscribe(), Some(filter_fn))
+    ) -> DropGuard
+    where
+        E: Encoder<RecordedEvents>,
+    {
+        let priority_rules = self.priority_rules.clone();
+        self.start::<BatchedEvents>(recorder.subscribe(), Some(filter_fn), move |event| {
+            priority_rules.priority(event)
+        })
     }
 
     /// If you want send more data than just metrics event, this generic method allow you to provide a custom [Batch]
     /// implementation and channel for [Batch::Item]s.
+    ///
+    /// All items are added with [Priority::Normal].
     pub fn start_with_receiver<B: Batch>(
         self,
         rx: broadcast::Receiver<B::Item>,
         filter_fn: Option<impl Fn(&B::Item) -> bool + Copy + 'static>,
-    ) -> DropGuard {
+    ) -> DropGuard
+    where
+        E: Encoder<B::CompletedBatch>,
+    {
+        self.start::<B>(rx, filter_fn, |_| Priority::Normal)
+    }
+
+    fn start<B: Batch>(
+        self,
+        rx: broadcast::Receiver<B::Item>,
+        filter_fn: Option<impl Fn(&B::Item) -> bool + Copy + 'static>,
+        priority_fn: impl Fn(&B::Item) -> Priority + 'static,
+    ) -> DropGuard
+    where
+        E: Encoder<B::CompletedBatch>,
+    {
         let token = CancellationToken::new();
 
         wasm_bindgen_futures::spawn_local({
@@ -170,22 +424,50 @@ impl<T: Transport + Send + 'static> MetricsHttpSender<T> {
                     }
                 });
 
-                self.run_transport::<B>(stream, token).await;
+                self.run_transport::<B>(stream, priority_fn, token).await;
             }
         });
 
         token.drop_guard()
     }
 
-    async fn run_transport<B: Batch>(self, stream: impl Stream<Item = B::Item>, token: CancellationToken) {
+    async fn run_transport<B: Batch>(
+        self,
+        stream: impl Stream<Item = B::Item>,
+        priority_fn: impl Fn(&B::Item) -> Priority,
+        token: CancellationToken,
+    ) where
+        E: Encoder<B::CompletedBatch>,
+    {
         let Self {
             max_chunk_size: buffer_size,
-            send_frequency,
+            max_critical_events,
+            chunk_overflow,
+            max_payload_size,
+            priority_rules: _,
+            mut send_frequency,
             self_metrics,
+            on_error,
             mut transport,
+            mut encoder,
+            session,
         } = self;
+        let report_error = |err: &TransportError| {
+            if let Some(on_error) = &on_error {
+                on_error(err);
+            }
+        };
 
+        let max_critical_events = max_critical_events.or(buffer_size);
         transport.enable_self_metrics(self_metrics);
+        transport.set_content_type(encoder.content_type());
+        let mut session = session.unwrap_or_default();
+        let session_id = session.session_id.get_or_insert_with(random_id).clone();
+        encoder.set_session(&session);
+        // The session id may be reused across starts, the nonce keeps the numbers of this start apart.
+        let nonce = random_id();
+        // The number of the next payload of this start, see [PayloadMetadata].
+        let mut sequence = 0;
 
         debug!("starting metrics transport");
         defer! {
@@ -213,6 +495,11 @@ impl<T: Transport + Send + 'static> MetricsHttpSender<T> {
         let mut time_to_send: Option<wasmtimer::tokio::Sleep> = None;
         let mut batch = B::new();
         let mut last_warning = None::<Instant>;
+        // The smallest payload size the server rejected with 413 Payload Too Large.
+        let mut rejected_payload_size = None::<usize>;
+        let mut remote_filter = RemoteFilter::default();
+        // Set by a `Retry-After` of the server, see [send_with_retry].
+        let paused_until = Cell::new(None::<Instant>);
 
         let mut stream = std::pin::pin!(stream);
 
@@ -235,49 +522,120 @@ impl<T: Transport + Send + 'static> MetricsHttpSender<T> {
                     time_to_send = None;
 
 
-                    let encoded = match batch.finalize().encode() {
-                        Ok(encoded) => Bytes::from(encoded),
-                        Err(err) => {
-                            error!(?err, "failed to encode metrics");
-                            continue;
-                        }
+                    let chunks = match (chunk_overflow, buffer_size) {
+                        (ChunkOverflow::Split, Some(buffer_size)) => batch.finalize_chunks(buffer_size),
+                        _ => vec![batch.finalize()],
                     };
-                    let post = || async { transport.send(&encoded).await };
-                    match post
-                        .retry(
-                            ExponentialBuilder::new()
-                                .with_max_times(5)
-                                .with_factor(2.0)
-                                .with_min_delay(Duration::from_secs(1))
-                                .with_max_delay(Duration::from_secs(60))
-                                .with_total_delay(Some(Duration::from_secs(3 * 60))),
-                        )
-                        .notify(|err: &std::io::Error, dur: Duration| {
-                            warn!(?err, "failed to send metrics, retrying in {dur:?}: {err}");
-                        })
-                        .await
-                    {
-                        Ok(_) => {
-                            if let Some(metrics_processed_counter) = &metrics_processed_counter {
-                                metrics_processed_counter.increment(n as _);
+
+                    let mut chunks = VecDeque::from(chunks);
+                    let mut failed_chunks = 0;
+                    while let Some(chunk) = chunks.pop_front() {
+                        let metadata = PayloadMetadata {
+                            session_id: session_id.clone(),
+                            nonce: nonce.clone(),
+                            sequence,
+                        };
+                        encoder.set_sequence(&metadata);
+                        let encoded = match encoder.encode_async(&chunk).await {
+                            Ok(encoded) => Bytes::from(encoded),
+                            Err(err) => {
+                                error!(?err, "failed to encode metrics");
+                                report_error(&TransportError::Encode(err));
+                                failed_chunks += 1;
+                                // Skip the number so that the server notices the lost payload.
+                                sequence += 1;
+                                continue;
+                            }
+                        };
+
+                        let too_large = (chunk_overflow == ChunkOverflow::Split
+                            && max_payload_size.is_some_and(|max_payload_size| encoded.len() > max_payload_size))
+                            || rejected_payload_size.is_some_and(|rejected| encoded.len() >= rejected);
+                        let chunk = if too_large {
+                            match B::split_completed(chunk) {
+                                Ok((first, second)) => {
+                                    chunks.push_front(second);
+                                    chunks.push_front(first);
+                                    continue;
+                                }
+                                Err(chunk) => {
+                                    warn!(size = %encoded.len(), "metrics payload too large and can't be split further");
+                                    chunk
+                                }
+                            }
+                        } else {
+                            chunk
+                        };
+
+                        if let Err(err) = send_with_retry(&transport, &encoded, &metadata, &paused_until).await {
+                            if err.status() == Some(413) {
+                                warn!(size = %encoded.len(), "metrics payload rejected as too large, splitting");
+                                rejected_payload_size =
+                                    Some(rejected_payload_size.map_or(encoded.len(), |size| size.min(encoded.len())));
+                                // The halves get new numbers: the rejected number must not come with a different
+                                // body, the server reports it missing.
+                                sequence += 1;
+                                if let Ok((first, second)) = B::split_completed(chunk) {
+                                    chunks.push_front(second);
+                                    chunks.push_front(first);
+                                    continue;
+                                }
+                            } else {
+                                sequence += 1;
                             }
-                            trace!(%n, "metrics send");
+                            error!(?err, "failed to send metrics chunk, giving up");
+                            report_error(&err);
+                            failed_chunks += 1;
+                        } else {
+                            encoder.commit(&chunk);
+                            sequence += 1;
                         }
-                        Err(err) => {
-                            error!(?err, "failed to send metrics, giving up and loosing {n} metrics");
+                    }
+
+                    if failed_chunks == 0 {
+                        if let Some(metrics_processed_counter) = &metrics_processed_counter {
+                            metrics_processed_counter.increment(n as _);
                         }
+                        trace!(%n, "metrics send");
+                    } else {
+                        error!("failed to send {failed_chunks} metrics chunk(s), loosing metrics");
+                    }
+
+                    if let Some(config) = transport.take_remote_config() {
+                        debug!(?config, "applying exporter config sent by the server");
+                        transport.apply_remote_config(&config);
+                        if let Some(frequency) = config.send_frequency {
+                            send_frequency = frequency.clamp(MIN_REMOTE_SEND_FREQUENCY, MAX_REMOTE_SEND_FREQUENCY);
+                        }
+                        remote_filter.update(config);
                     }
                 }
 
                 Some(event) = stream.next() => {
-                    if buffer_size.is_some_and(|buffer_size| batch.len() >= buffer_size) {
+                    let priority = priority_fn(&event);
+                    if priority != Priority::Critical
+                        && B::metric_name(&event).is_some_and(|name| !remote_filter.keep(name, priority))
+                    {
+                        continue;
+                    }
+                    let full = chunk_overflow == ChunkOverflow::DropOldest
+                        && if priority == Priority::Critical {
+                            max_critical_events.is_some_and(|max| batch.len_with_priority(Priority::Critical) >= max)
+                        } else {
+                            buffer_size.is_some_and(|buffer_size| {
+                                batch.len() - batch.len_with_priority(Priority::Critical) >= buffer_size
+                            })
+                        };
+                    if full {
                         if last_warning.is_none_or(|last_warning| last_warning.elapsed() >= Duration::from_secs(5)) {
                             warn!("metrics chunk size exceeded, dropping metrics");
                             last_warning = Some(Instant::now());
                         }
-                        batch.pop_front();
-                    };
-                    batch.push_back(event);
+                        if priority == Priority::Critical || !make_room(&mut batch, priority) {
+                            continue;
+                        }
+                    }
+                    batch.push_back_with_priority(event, priority);
                     if time_to_send.is_none() {
                         time_to_send = Some(sleep(send_frequency));
                     }
@@ -286,3 +644,233 @@ impl<T: Transport + Send + 'static> MetricsHttpSender<T> {
         }
     }
 }
+
+/// Evict the oldest event to make room for an event of `priority`: a [Priority::Low] one, or a [Priority::Normal] one
+/// if there is no low priority event and the new event isn't low priority either. Returns `false` if there is nothing
+/// to evict, the new event is dropped then.
+fn make_room<B: Batch>(batch: &mut B, priority: Priority) -> bool {
+    [Priority::Low, Priority::Normal]
+        .into_iter()
+        .filter(|evictable| *evictable <= priority)
+        .any(|evictable| batch.evict(evictable).is_some())
+}
+
+/// Sampling rules and disabled metrics of the last [ExporterConfig] sent by the server.
+#[derive(Default)]
+struct RemoteFilter {
+    /// Prefix, rate and the accumulated rate of the events seen so far.
+    sampling: Vec<(String, f64, f64)>,
+    disabled: HashSet<String>,
+}
+
+impl RemoteFilter {
+    fn update(&mut self, config: ExporterConfig) {
+        self.sampling = config
+            .sampling
+            .into_iter()
+            .map(|rule| (rule.prefix, rule.rate.clamp(0.0, 1.0), 0.0))
+            .collect();
+        self.disabled = config.disabled_metrics.into_iter().collect();
+    }
+
+    /// Sampling is deterministic: every event adds the rate of its rule to an accumulator and is kept whenever the
+    /// accumulator reaches 1. A rate of 0.25 keeps every fourth event. Only [Priority::Low] events are sampled,
+    /// disabled metrics are dropped regardless of their priority.
+    fn keep(&mut self, name: &str, priority: Priority) -> bool {
+        if self.disabled.contains(name) {
+            return false;
+        }
+        if priority != Priority::Low {
+            return true;
+        }
+        let Some((_, rate, acc)) = self
+            .sampling
+            .iter_mut()
+            .filter(|(prefix, ..)| name.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, ..)| prefix.len())
+        else {
+            return true;
+        };
+        *acc += *rate;
+        if *acc >= 1.0 {
+            *acc -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The longest delay between retries, also the upper bound of a `Retry-After` from the server.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Retries failed sends with exponential backoff. Errors that won't go away (see [TransportError::is_retryable]) are
+/// not retried and a `Retry-After` from the server replaces the backoff delay, up to [MAX_RETRY_DELAY].
+///
+/// A `Retry-After` also pauses later sends: no request is made before `paused_until`.
+async fn send_with_retry<T: Transport>(
+    transport: &T,
+    payload: &Bytes,
+    metadata: &PayloadMetadata,
+    paused_until: &Cell<Option<Instant>>,
+) -> Result<(), TransportError> {
+    let post = || async {
+        if let Some(until) = paused_until.take() {
+            let now = Instant::now();
+            if until > now {
+                sleep(until - now).await;
+            }
+        }
+        let result = transport.send_with_metadata(payload, metadata).await;
+        if let Some(retry_after) = result.as_ref().err().and_then(TransportError::retry_after) {
+            paused_until.set(Some(Instant::now() + retry_after.min(MAX_RETRY_DELAY)));
+        }
+        result
+    };
+    post.retry(
+        ExponentialBuilder::new()
+            .with_max_times(5)
+            .with_factor(2.0)
+            .with_min_delay(Duration::from_secs(1))
+            .with_max_delay(MAX_RETRY_DELAY)
+            .with_total_delay(Some(Duration::from_secs(3 * 60))),
+    )
+    .when(TransportError::is_retryable)
+    .adjust(|err: &TransportError, dur| {
+        // Keep giving up once the backoff is exhausted, otherwise wait as long as the server asked for. The pause is
+        // already waited for before the next attempt.
+        dur.map(|dur| err.retry_after().map_or(dur, |_| Duration::ZERO))
+    })
+    .notify(|err: &TransportError, dur: Duration| {
+        warn!(?err, "failed to send metrics, retrying in {dur:?}: {err}");
+    })
+    .await
+}
+
+/// 64 random bits as hex. Not cryptographically secure, only used to tell sessions and starts apart.
+fn random_id() -> String {
+    let random = || (web_sys::js_sys::Math::random() * u32::MAX as f64) as u32;
+    format!("{:08x}{:08x}", random(), random())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MetricOperation;
+    use metrics::Key;
+    use metrics_exporter_wasm_core::SamplingRule;
+
+    fn metric(name: &str) -> Event {
+        Event::Metric {
+            key: Key::from_name(name.to_string()),
+            op: MetricOperation::IncrementCounter(1),
+        }
+    }
+
+    fn batch(names: &[&str]) -> BatchedEvents {
+        let mut batch = BatchedEvents::new();
+        for name in names {
+            batch.push_back(metric(name));
+        }
+        batch
+    }
+
+    fn names(events: RecordedEvents) -> Vec<String> {
+        Vec::<RecordedEvent>::try_from(events)
+            .unwrap()
+            .into_iter()
+            .map(|event| match event.event {
+                Event::Metric { key, .. } => key.name().to_string(),
+                Event::Description { name, .. } => name.as_str().to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finalize_chunks_in_order() {
+        let mut events = batch(&["a", "b", "c", "d", "e", "f", "g"]);
+        let chunks = events.finalize_chunks(3).into_iter().map(names).collect::<Vec<_>>();
+        assert_eq!(chunks, [vec!["a", "b", "c"], vec!["d", "e", "f"], vec!["g"]]);
+        assert!(events.is_empty());
+
+        let chunks = batch(&["a", "b", "c"]).finalize_chunks(3);
+        assert_eq!(chunks.len(), 1);
+    }
+
+    #[test]
+    fn split_completed_until_one_item() {
+        let chunk = batch(&["a", "b", "c", "d", "e"]).finalize();
+        let Ok((first, second)) = BatchedEvents::split_completed(chunk) else {
+            panic!("a chunk of 5 items must split");
+        };
+        assert_eq!(names(first.clone()), ["a", "b"]);
+        assert_eq!(names(second), ["c", "d", "e"]);
+
+        let Ok((first, second)) = BatchedEvents::split_completed(first) else {
+            panic!("a chunk of 2 items must split");
+        };
+        assert_eq!(names(first.clone()), ["a"]);
+        assert_eq!(names(second), ["b"]);
+
+        let Err(single) = BatchedEvents::split_completed(first) else {
+            panic!("a chunk of 1 item must not split");
+        };
+        assert_eq!(names(single), ["a"]);
+    }
+
+    #[test]
+    fn evicts_oldest_low_priority_event() {
+        let mut events = BatchedEvents::new();
+        events.push_back_with_priority(metric("normal"), Priority::Normal);
+        events.push_back_with_priority(metric("low1"), Priority::Low);
+        events.push_back_with_priority(metric("critical"), Priority::Critical);
+        events.push_back_with_priority(metric("low2"), Priority::Low);
+        assert_eq!(events.len_with_priority(Priority::Low), 2);
+
+        let evicted = events
+            .evict(Priority::Low)
+            .and_then(|event| BatchedEvents::metric_name(&event).map(String::from));
+        assert_eq!(evicted.as_deref(), Some("low1"));
+        let evicted = events
+            .evict(Priority::Low)
+            .and_then(|event| BatchedEvents::metric_name(&event).map(String::from));
+        assert_eq!(evicted.as_deref(), Some("low2"));
+        assert!(events.evict(Priority::Low).is_none());
+
+        assert_eq!(events.len_with_priority(Priority::Critical), 1);
+        assert_eq!(names(events.finalize()), ["normal", "critical"]);
+        assert_eq!(events.len_with_priority(Priority::Critical), 0);
+    }
+
+    #[test]
+    fn make_room_evicts_normal_events_without_low_ones() {
+        let mut events = BatchedEvents::new();
+        events.push_back_with_priority(metric("normal1"), Priority::Normal);
+        events.push_back_with_priority(metric("low"), Priority::Low);
+        events.push_back_with_priority(metric("normal2"), Priority::Normal);
+
+        assert!(make_room(&mut events, Priority::Normal));
+        assert_eq!(events.len_with_priority(Priority::Low), 0);
+        // A low priority event never evicts a normal one.
+        assert!(!make_room(&mut events, Priority::Low));
+        assert!(make_room(&mut events, Priority::Normal));
+        assert_eq!(names(events.finalize()), ["normal2"]);
+    }
+
+    #[test]
+    fn samples_only_low_priority_events() {
+        let mut filter = RemoteFilter::default();
+        filter.update(ExporterConfig {
+            sampling: vec![SamplingRule {
+                prefix: "frame".to_string(),
+                rate: 0.5,
+            }],
+            disabled_metrics: vec!["frame_debug".to_string()],
+            ..Default::default()
+        });
+        let kept = |filter: &mut RemoteFilter, priority| (0..4).filter(|_| filter.keep("frame_time", priority)).count();
+        assert_eq!(kept(&mut filter, Priority::Low), 2);
+        assert_eq!(kept(&mut filter, Priority::Normal), 4);
+        assert!(!filter.keep("frame_debug", Priority::Normal));
+    }
+}
@@ -0,0 +1,965 @@
+//! Export of recorded metrics events to an [OpenTelemetry](https://opentelemetry.io/docs/specs/otlp/) collector using
+//! OTLP/HTTP with protobuf encoding.
+//!
+//! ```no_run
+//! use metrics_exporter_wasm::{otlp::OtlpHttpTransport, HttpPostTransport, MetricsHttpSender, WasmRecorder};
+//!
+//! # fn main() {
+//! let recorder = WasmRecorder::builder().build().expect("failed to create recorder");
+//! let transport = OtlpHttpTransport::new(HttpPostTransport::new().endpoint("https://collector.example.com/v1/metrics"))
+//!     .service_name("my-app");
+//! MetricsHttpSender::new(transport)
+//!     .start_with_metrics_recorder(&recorder)
+//!     .disarm();
+//! # }
+//! ```
+
+use crate::{
+    http_transport::EndpointDefined,
+    Encoder,
+    HttpPostTransport,
+    Transport,
+    TransportError,
+};
+use bytes::Bytes;
+use metrics::{
+    Key,
+    Unit,
+};
+use metrics_exporter_wasm_core::{
+    util_time,
+    ClientSession,
+    DecodeLimits,
+    Event,
+    MetricOperation,
+    PayloadMetadata,
+    PayloadVersion,
+    RecordedEvent,
+    RecordedEvents,
+};
+use prost::Message as _;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    future::Future,
+    io,
+};
+
+const CONTENT_TYPE: &str = "application/x-protobuf";
+
+/// A [Transport] that converts the ASN.1 encoded [RecordedEvents] payloads into an OTLP `ExportMetricsServiceRequest`
+/// and posts it with the wrapped [HttpPostTransport].
+///
+/// This expects the [crate::Asn1Encoder] in any [PayloadVersion]. Alternatively, use the [OtlpConverter] as
+/// [Encoder] of the [crate::MetricsHttpSender] together with a plain [HttpPostTransport].
+///
+/// The [ClientSession] of [PayloadVersion::V1] payloads becomes resource attributes, see [OtlpConverter]. Version 0
+/// payloads carry no session.
+pub struct OtlpHttpTransport {
+    inner: HttpPostTransport<EndpointDefined>,
+    converter: RefCell<OtlpConverter>,
+    // The version of the incoming payloads, the content type of the inner transport is always protobuf.
+    version: PayloadVersion,
+}
+
+impl OtlpHttpTransport {
+    /// Wrap a [HttpPostTransport]. Its endpoint should be the collector's metrics endpoint, usually ending in
+    /// `/v1/metrics`.
+    pub fn new(mut inner: HttpPostTransport<EndpointDefined>) -> Self {
+        inner.set_content_type(CONTENT_TYPE);
+        Self {
+            inner,
+            converter: RefCell::new(OtlpConverter::new()),
+            version: PayloadVersion::default(),
+        }
+    }
+
+    /// Set the `service.name` resource attribute, see [OtlpConverter::service_name].
+    pub fn service_name(self, service_name: impl ToString) -> Self {
+        self.map_converter(|converter| converter.service_name(service_name))
+    }
+
+    /// Add a resource attribute, see [OtlpConverter::resource_attribute].
+    pub fn resource_attribute(self, key: impl ToString, value: impl ToString) -> Self {
+        self.map_converter(|converter| converter.resource_attribute(key, value))
+    }
+
+    fn map_converter(mut self, f: impl FnOnce(OtlpConverter) -> OtlpConverter) -> Self {
+        self.converter = RefCell::new(f(self.converter.into_inner()));
+        self
+    }
+
+    /// Convert without updating the converter state, retries and split payloads are converted again.
+    fn convert(&self, payload: &Bytes) -> io::Result<(Vec<RecordedEvent>, Bytes)> {
+        // The payload was encoded by this exporter, so no limits are needed.
+        let payload = self.version.decode_with_session(payload, &DecodeLimits::UNLIMITED)?;
+        if let Some(session) = &payload.session {
+            self.converter.borrow_mut().set_session(session);
+        }
+        let events: Vec<RecordedEvent> = payload.events.try_into()?;
+        let request = self.converter.borrow().export_request(events.clone());
+        Ok((events, Bytes::from(request.encode_to_vec())))
+    }
+
+    async fn send_converted(&self, payload: &Bytes, metadata: Option<&PayloadMetadata>) -> Result<(), TransportError> {
+        let (events, converted) = self.convert(payload).map_err(TransportError::Encode)?;
+        match metadata {
+            Some(metadata) => self.inner.send_with_metadata(&converted, metadata).await?,
+            None => self.inner.send(&converted).await?,
+        }
+        self.converter.borrow_mut().commit_events(&events);
+        Ok(())
+    }
+}
+
+impl Transport for OtlpHttpTransport {
+    fn enable_self_metrics(&mut self, self_metrics: bool) {
+        self.inner.enable_self_metrics(self_metrics);
+    }
+
+    fn set_content_type(&mut self, content_type: &'static str) {
+        if let Ok(version) = PayloadVersion::from_content_type(Some(content_type)) {
+            self.version = version;
+        }
+    }
+
+    fn send(&self, payload: &Bytes) -> impl Future<Output = Result<(), TransportError>> {
+        self.send_converted(payload, None)
+    }
+
+    fn send_with_metadata(
+        &self,
+        payload: &Bytes,
+        metadata: &PayloadMetadata,
+    ) -> impl Future<Output = Result<(), TransportError>> {
+        self.send_converted(payload, Some(metadata))
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// Converts [RecordedEvent]s into OTLP metrics, keeping the state needed across batches.
+///
+/// - Counter increments become monotonic sums with delta temporality, absolute counter values become cumulative sums.
+/// - Gauge operations become gauges. Increments and decrements are applied to the last known value.
+/// - Every recorded histogram value becomes a histogram data point with delta temporality and a single bucket, so the
+///   original timestamps are kept.
+/// - [Event::Description]s provide description and unit of subsequently exported metrics.
+///
+/// The [ClientSession] (see [Encoder::set_session]) is exported as resource attributes: `session.id`,
+/// `service.instance.id`, `service.version`, `user_agent.original` and the session's own resource attributes.
+/// Attributes added with [Self::resource_attribute] take precedence.
+///
+/// As [Encoder], the state is only updated once a payload was sent (see [Encoder::commit]).
+#[derive(Debug)]
+pub struct OtlpConverter {
+    resource_attributes: Vec<(String, String)>,
+    session: Option<ClientSession>,
+    start_time_unix_nano: u64,
+    state: ConverterState,
+}
+
+/// What the [OtlpConverter] remembers across batches.
+#[derive(Debug, Default, Clone)]
+struct ConverterState {
+    descriptions: HashMap<String, (String, Option<Unit>)>,
+    gauges: HashMap<Key, f64>,
+    last_increments: HashMap<Key, u64>,
+}
+
+impl ConverterState {
+    fn record(&mut self, RecordedEvent { timestamp, event }: &RecordedEvent) {
+        match event {
+            Event::Description {
+                name,
+                unit,
+                description,
+                ..
+            } => {
+                self.descriptions
+                    .insert(name.as_str().to_string(), (description.to_string(), *unit));
+            }
+            Event::Metric { key, op } => match *op {
+                MetricOperation::IncrementCounter(_) => {
+                    self.last_increments.insert(key.clone(), unix_nanos(*timestamp));
+                }
+                MetricOperation::IncrementGauge(value) => *self.gauges.entry(key.clone()).or_default() += value,
+                MetricOperation::DecrementGauge(value) => *self.gauges.entry(key.clone()).or_default() -= value,
+                MetricOperation::SetGauge(value) => {
+                    self.gauges.insert(key.clone(), value);
+                }
+                MetricOperation::SetCounter(_) | MetricOperation::RecordHistogram(_) => {}
+            },
+        }
+    }
+}
+
+impl Default for OtlpConverter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum MetricKind {
+    DeltaSum,
+    CumulativeSum,
+    Gauge,
+    Histogram,
+}
+
+impl OtlpConverter {
+    pub fn new() -> Self {
+        Self {
+            resource_attributes: Vec::new(),
+            session: None,
+            start_time_unix_nano: unix_nanos(util_time::now()),
+            state: ConverterState::default(),
+        }
+    }
+
+    /// Set the `service.name` resource attribute. OTLP backends expect every service to set it.
+    pub fn service_name(self, service_name: impl ToString) -> Self {
+        self.resource_attribute("service.name", service_name)
+    }
+
+    /// Add a resource attribute that is attached to all exported metrics.
+    pub fn resource_attribute(mut self, key: impl ToString, value: impl ToString) -> Self {
+        self.resource_attributes.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Convert a batch of events into an export request and remember the state needed for the next batch.
+    pub fn convert(&mut self, events: Vec<RecordedEvent>) -> proto::ExportMetricsServiceRequest {
+        let mut state = std::mem::take(&mut self.state);
+        let request = self.convert_with_state(events, &mut state);
+        self.state = state;
+        request
+    }
+
+    /// Like [Self::convert], but leaves the state unchanged.
+    fn export_request(&self, events: Vec<RecordedEvent>) -> proto::ExportMetricsServiceRequest {
+        self.convert_with_state(events, &mut self.state.clone())
+    }
+
+    fn resource_attributes(&self) -> Vec<proto::KeyValue> {
+        let mut attributes: Vec<(&str, &str)> = self
+            .resource_attributes
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        if let Some(session) = &self.session {
+            let well_known = [
+                ("session.id", &session.session_id),
+                ("service.instance.id", &session.client_instance_id),
+                ("service.version", &session.app_version),
+                ("user_agent.original", &session.user_agent),
+            ];
+            let session_attributes = well_known
+                .into_iter()
+                .filter_map(|(key, value)| Some((key, value.as_deref()?)))
+                .chain(
+                    session
+                        .resource_attributes
+                        .iter()
+                        .map(|(key, value)| (key.as_str(), value.as_str())),
+                );
+            for (key, value) in session_attributes {
+                if !attributes.iter().any(|(existing, _)| *existing == key) {
+                    attributes.push((key, value));
+                }
+            }
+        }
+        attributes
+            .into_iter()
+            .map(|(key, value)| string_attribute(key, value))
+            .collect()
+    }
+
+    fn commit_events(&mut self, events: &[RecordedEvent]) {
+        for event in events {
+            self.state.record(event);
+        }
+    }
+
+    fn convert_with_state(
+        &self,
+        events: Vec<RecordedEvent>,
+        state: &mut ConverterState,
+    ) -> proto::ExportMetricsServiceRequest {
+        let mut metrics: Vec<proto::Metric> = Vec::new();
+        let mut index: HashMap<(String, MetricKind), usize> = HashMap::new();
+
+        for event in events {
+            let last_increment = match &event.event {
+                Event::Metric {
+                    key,
+                    op: MetricOperation::IncrementCounter(_),
+                } => state.last_increments.get(key).copied(),
+                _ => None,
+            };
+            state.record(&event);
+
+            let RecordedEvent { timestamp, event } = event;
+            let time_unix_nano = unix_nanos(timestamp);
+            let Event::Metric { key, op } = event else {
+                continue;
+            };
+
+            let attributes = key
+                .labels()
+                .map(|label| string_attribute(label.key(), label.value()))
+                .collect::<Vec<_>>();
+
+            let (kind, data_point) = match op {
+                MetricOperation::IncrementCounter(value) => {
+                    let start_time_unix_nano = last_increment.unwrap_or(time_unix_nano);
+                    let point = number_point(attributes, start_time_unix_nano, time_unix_nano, int_value(value));
+                    (MetricKind::DeltaSum, DataPoint::Number(point))
+                }
+                MetricOperation::SetCounter(value) => {
+                    let point = number_point(attributes, self.start_time_unix_nano, time_unix_nano, int_value(value));
+                    (MetricKind::CumulativeSum, DataPoint::Number(point))
+                }
+                MetricOperation::IncrementGauge(_)
+                | MetricOperation::DecrementGauge(_)
+                | MetricOperation::SetGauge(_) => {
+                    let value = state.gauges.get(&key).copied().unwrap_or_default();
+                    let point = number_point(attributes, 0, time_unix_nano, double_value(value));
+                    (MetricKind::Gauge, DataPoint::Number(point))
+                }
+                MetricOperation::RecordHistogram(value) => {
+                    let point = proto::HistogramDataPoint {
+                        attributes,
+                        start_time_unix_nano: time_unix_nano,
+                        time_unix_nano,
+                        count: 1,
+                        sum: Some(value),
+                        bucket_counts: vec![1],
+                        explicit_bounds: Vec::new(),
+                        flags: 0,
+                        min: Some(value),
+                        max: Some(value),
+                    };
+                    (MetricKind::Histogram, DataPoint::Histogram(point))
+                }
+            };
+
+            let name = key.name().to_string();
+            let i = *index.entry((name.clone(), kind)).or_insert_with(|| {
+                metrics.push(metric(name, kind, state));
+                metrics.len() - 1
+            });
+
+            match (&mut metrics[i].data, data_point) {
+                (Some(proto::metric::Data::Sum(sum)), DataPoint::Number(point)) => sum.data_points.push(point),
+                (Some(proto::metric::Data::Gauge(gauge)), DataPoint::Number(point)) => gauge.data_points.push(point),
+                (Some(proto::metric::Data::Histogram(histogram)), DataPoint::Histogram(point)) => {
+                    histogram.data_points.push(point)
+                }
+                _ => unreachable!("metric kind and data point type always match"),
+            }
+        }
+
+        proto::ExportMetricsServiceRequest {
+            resource_metrics: vec![proto::ResourceMetrics {
+                resource: Some(proto::Resource {
+                    attributes: self.resource_attributes(),
+                    dropped_attributes_count: 0,
+                }),
+                scope_metrics: vec![proto::ScopeMetrics {
+                    scope: Some(proto::InstrumentationScope {
+                        name: env!("CARGO_PKG_NAME").to_string(),
+                        version: env!("CARGO_PKG_VERSION").to_string(),
+                        attributes: Vec::new(),
+                        dropped_attributes_count: 0,
+                    }),
+                    metrics,
+                    schema_url: String::new(),
+                }],
+                schema_url: String::new(),
+            }],
+        }
+    }
+}
+
+fn metric(name: String, kind: MetricKind, state: &ConverterState) -> proto::Metric {
+    let (description, unit) = state
+        .descriptions
+        .get(&name)
+        .map(|(description, unit)| (description.clone(), unit.map(ucum_unit).unwrap_or_default()))
+        .unwrap_or_default();
+
+    let data = match kind {
+        MetricKind::DeltaSum => proto::metric::Data::Sum(proto::Sum {
+            data_points: Vec::new(),
+            aggregation_temporality: proto::AggregationTemporality::Delta as i32,
+            is_monotonic: true,
+        }),
+        MetricKind::CumulativeSum => proto::metric::Data::Sum(proto::Sum {
+            data_points: Vec::new(),
+            aggregation_temporality: proto::AggregationTemporality::Cumulative as i32,
+            is_monotonic: true,
+        }),
+        MetricKind::Gauge => proto::metric::Data::Gauge(proto::Gauge {
+            data_points: Vec::new(),
+        }),
+        MetricKind::Histogram => proto::metric::Data::Histogram(proto::Histogram {
+            data_points: Vec::new(),
+            aggregation_temporality: proto::AggregationTemporality::Delta as i32,
+        }),
+    };
+
+    proto::Metric {
+        name,
+        description,
+        unit: unit.to_string(),
+        data: Some(data),
+    }
+}
+
+impl Encoder<RecordedEvents> for OtlpConverter {
+    fn content_type(&self) -> &'static str {
+        CONTENT_TYPE
+    }
+
+    fn encode(&self, batch: &RecordedEvents) -> io::Result<Vec<u8>> {
+        Ok(self.export_request(batch.clone().try_into()?).encode_to_vec())
+    }
+
+    fn commit(&mut self, batch: &RecordedEvents) {
+        // Conversion already succeeded in encode.
+        if let Ok(events) = Vec::<RecordedEvent>::try_from(batch.clone()) {
+            self.commit_events(&events);
+        }
+    }
+
+    fn set_session(&mut self, session: &ClientSession) {
+        self.session = Some(session.clone());
+    }
+}
+
+enum DataPoint {
+    Number(proto::NumberDataPoint),
+    Histogram(proto::HistogramDataPoint),
+}
+
+fn unix_nanos(timestamp: chrono::DateTime<chrono::Utc>) -> u64 {
+    timestamp.timestamp_nanos_opt().unwrap_or_default().max(0) as u64
+}
+
+fn number_point(
+    attributes: Vec<proto::KeyValue>,
+    start_time_unix_nano: u64,
+    time_unix_nano: u64,
+    value: proto::number_data_point::Value,
+) -> proto::NumberDataPoint {
+    proto::NumberDataPoint {
+        attributes,
+        start_time_unix_nano,
+        time_unix_nano,
+        flags: 0,
+        value: Some(value),
+    }
+}
+
+fn int_value(value: u64) -> proto::number_data_point::Value {
+    proto::number_data_point::Value::AsInt(value.min(i64::MAX as u64) as i64)
+}
+
+fn double_value(value: f64) -> proto::number_data_point::Value {
+    proto::number_data_point::Value::AsDouble(value)
+}
+
+fn string_attribute(key: &str, value: &str) -> proto::KeyValue {
+    proto::KeyValue {
+        key: key.to_string(),
+        value: Some(proto::AnyValue {
+            value: Some(proto::any_value::Value::StringValue(value.to_string())),
+        }),
+    }
+}
+
+/// The [UCUM](https://ucum.org/) representation of a unit, as recommended by OpenTelemetry.
+fn ucum_unit(unit: Unit) -> &'static str {
+    match unit {
+        Unit::Count => "1",
+        Unit::Percent => "%",
+        Unit::Seconds => "s",
+        Unit::Milliseconds => "ms",
+        Unit::Microseconds => "us",
+        Unit::Nanoseconds => "ns",
+        Unit::Tebibytes => "TiBy",
+        Unit::Gibibytes => "GiBy",
+        Unit::Mebibytes => "MiBy",
+        Unit::Kibibytes => "KiBy",
+        Unit::Bytes => "By",
+        Unit::TerabitsPerSecond => "Tbit/s",
+        Unit::GigabitsPerSecond => "Gbit/s",
+        Unit::MegabitsPerSecond => "Mbit/s",
+        Unit::KilobitsPerSecond => "kbit/s",
+        Unit::BitsPerSecond => "bit/s",
+        Unit::CountPerSecond => "1/s",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics_exporter_wasm_core::MetricType;
+    use proto::{
+        metric::Data,
+        number_data_point::Value,
+    };
+
+    fn at(offset_ms: i64, event: Event) -> RecordedEvent {
+        let start = chrono::DateTime::from_timestamp(1_714_564_800, 0).unwrap();
+        RecordedEvent {
+            timestamp: start + chrono::Duration::milliseconds(offset_ms),
+            event,
+        }
+    }
+
+    fn metric(name: &'static str, op: MetricOperation) -> Event {
+        Event::Metric {
+            key: Key::from_name(name),
+            op,
+        }
+    }
+
+    fn find<'a>(request: &'a proto::ExportMetricsServiceRequest, name: &str) -> Vec<&'a proto::Metric> {
+        request.resource_metrics[0].scope_metrics[0]
+            .metrics
+            .iter()
+            .filter(|metric| metric.name == name)
+            .collect()
+    }
+
+    fn number_points(metric: &proto::Metric) -> Vec<(u64, u64, Value)> {
+        let points = match &metric.data {
+            Some(Data::Sum(sum)) => &sum.data_points,
+            Some(Data::Gauge(gauge)) => &gauge.data_points,
+            data => panic!("not a number metric: {data:?}"),
+        };
+        points
+            .iter()
+            .map(|point| (point.start_time_unix_nano, point.time_unix_nano, point.value.unwrap()))
+            .collect()
+    }
+
+    fn nanos(offset_ms: i64) -> u64 {
+        unix_nanos(at(offset_ms, metric("", MetricOperation::SetCounter(0))).timestamp)
+    }
+
+    #[test]
+    fn counters() {
+        let mut converter = OtlpConverter::new();
+        let request = converter.convert(vec![
+            at(0, metric("requests", MetricOperation::IncrementCounter(1))),
+            at(10, metric("requests", MetricOperation::IncrementCounter(2))),
+            at(20, metric("total", MetricOperation::SetCounter(100))),
+        ]);
+
+        let [requests] = find(&request, "requests")[..] else {
+            panic!("expected one requests metric");
+        };
+        let Some(Data::Sum(sum)) = &requests.data else {
+            panic!("counter increments are sums");
+        };
+        assert_eq!(sum.aggregation_temporality, proto::AggregationTemporality::Delta as i32);
+        assert!(sum.is_monotonic);
+        assert_eq!(
+            number_points(requests),
+            [
+                (nanos(0), nanos(0), Value::AsInt(1)),
+                (nanos(0), nanos(10), Value::AsInt(2))
+            ]
+        );
+
+        let [total] = find(&request, "total")[..] else {
+            panic!("expected one total metric");
+        };
+        let Some(Data::Sum(sum)) = &total.data else {
+            panic!("absolute counter values are sums");
+        };
+        assert_eq!(
+            sum.aggregation_temporality,
+            proto::AggregationTemporality::Cumulative as i32
+        );
+        assert_eq!(
+            number_points(total),
+            [(converter.start_time_unix_nano, nanos(20), Value::AsInt(100))]
+        );
+
+        // The next delta starts where the last one of the previous batch ended.
+        let request = converter.convert(vec![at(30, metric("requests", MetricOperation::IncrementCounter(3)))]);
+        assert_eq!(
+            number_points(find(&request, "requests")[0]),
+            [(nanos(10), nanos(30), Value::AsInt(3))]
+        );
+    }
+
+    #[test]
+    fn gauges() {
+        let mut converter = OtlpConverter::new();
+        let request = converter.convert(vec![
+            at(0, metric("queue", MetricOperation::IncrementGauge(2.5))),
+            at(1, metric("queue", MetricOperation::DecrementGauge(1.0))),
+            at(2, metric("queue", MetricOperation::SetGauge(10.0))),
+        ]);
+        let values = number_points(find(&request, "queue")[0])
+            .into_iter()
+            .map(|(start, _, value)| (start, value))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            [
+                (0, Value::AsDouble(2.5)),
+                (0, Value::AsDouble(1.5)),
+                (0, Value::AsDouble(10.0))
+            ]
+        );
+
+        let request = converter.convert(vec![at(3, metric("queue", MetricOperation::IncrementGauge(1.0)))]);
+        assert_eq!(
+            number_points(find(&request, "queue")[0]),
+            [(0, nanos(3), Value::AsDouble(11.0))]
+        );
+    }
+
+    #[test]
+    fn histograms() {
+        let mut converter = OtlpConverter::new();
+        let request = converter.convert(vec![
+            at(0, metric("latency", MetricOperation::RecordHistogram(0.5))),
+            at(5, metric("latency", MetricOperation::RecordHistogram(1.5))),
+        ]);
+        let Some(Data::Histogram(histogram)) = &find(&request, "latency")[0].data else {
+            panic!("expected a histogram");
+        };
+        assert_eq!(
+            histogram.aggregation_temporality,
+            proto::AggregationTemporality::Delta as i32
+        );
+        let points = histogram
+            .data_points
+            .iter()
+            .map(|point| {
+                (
+                    point.start_time_unix_nano,
+                    point.time_unix_nano,
+                    point.count,
+                    point.sum,
+                    point.bucket_counts.clone(),
+                    point.explicit_bounds.clone(),
+                    point.min,
+                    point.max,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            points,
+            [
+                (nanos(0), nanos(0), 1, Some(0.5), vec![1], vec![], Some(0.5), Some(0.5)),
+                (nanos(5), nanos(5), 1, Some(1.5), vec![1], vec![], Some(1.5), Some(1.5)),
+            ]
+        );
+    }
+
+    #[test]
+    fn descriptions_and_units() {
+        let mut converter = OtlpConverter::new();
+        let request = converter.convert(vec![
+            at(
+                0,
+                Event::Description {
+                    name: "latency".into(),
+                    metric_type: MetricType::Histogram,
+                    unit: Some(Unit::Milliseconds),
+                    description: "Request latency".into(),
+                },
+            ),
+            at(1, metric("latency", MetricOperation::RecordHistogram(1.0))),
+            at(2, metric("requests", MetricOperation::IncrementCounter(1))),
+        ]);
+        let latency = find(&request, "latency")[0];
+        assert_eq!(
+            (latency.description.as_str(), latency.unit.as_str()),
+            ("Request latency", "ms")
+        );
+        let requests = find(&request, "requests")[0];
+        assert_eq!((requests.description.as_str(), requests.unit.as_str()), ("", ""));
+
+        // Descriptions apply to later batches too.
+        let request = converter.convert(vec![at(3, metric("latency", MetricOperation::RecordHistogram(2.0)))]);
+        assert_eq!(find(&request, "latency")[0].unit, "ms");
+    }
+
+    #[test]
+    fn encode_leaves_state_until_commit() {
+        let mut converter = OtlpConverter::new();
+        let batch = RecordedEvents::new(
+            at(0, metric("", MetricOperation::SetCounter(0))).timestamp,
+            vec![at(0, metric("queue", MetricOperation::IncrementGauge(1.0)))],
+        );
+        let first = converter.encode(&batch).unwrap();
+        assert_eq!(converter.encode(&batch).unwrap(), first);
+
+        converter.commit(&batch);
+        let request = proto::ExportMetricsServiceRequest::decode(&converter.encode(&batch).unwrap()[..]).unwrap();
+        assert_eq!(
+            number_points(find(&request, "queue")[0]),
+            [(0, nanos(0), Value::AsDouble(2.0))]
+        );
+    }
+
+    fn resource_attributes(request: &proto::ExportMetricsServiceRequest) -> Vec<(&str, &str)> {
+        let resource = request.resource_metrics[0].resource.as_ref().unwrap();
+        resource
+            .attributes
+            .iter()
+            .map(|attribute| match &attribute.value {
+                Some(proto::AnyValue {
+                    value: Some(proto::any_value::Value::StringValue(value)),
+                }) => (attribute.key.as_str(), value.as_str()),
+                value => panic!("unexpected attribute value {value:?}"),
+            })
+            .collect()
+    }
+
+    fn session() -> ClientSession {
+        ClientSession::new()
+            .session_id("session")
+            .app_version("1.2.3")
+            .user_agent("Firefox 131 / Linux")
+            .resource_attribute("page", "/player")
+            .resource_attribute("service.name", "from-session")
+    }
+
+    #[test]
+    fn session_resource_attributes() {
+        let mut converter = OtlpConverter::new().service_name("app");
+        converter.set_session(&session());
+        let request = converter.convert(vec![at(0, metric("requests", MetricOperation::IncrementCounter(1)))]);
+        assert_eq!(
+            resource_attributes(&request),
+            [
+                ("service.name", "app"),
+                ("session.id", "session"),
+                ("service.version", "1.2.3"),
+                ("user_agent.original", "Firefox 131 / Linux"),
+                ("page", "/player"),
+            ]
+        );
+    }
+
+    #[test]
+    fn transport_exports_payload_session() {
+        let mut transport = OtlpHttpTransport::new(HttpPostTransport::new().endpoint("http://localhost/v1/metrics"))
+            .service_name("app");
+        transport.set_content_type(PayloadVersion::V1.content_type());
+        let events = vec![at(0, metric("requests", MetricOperation::IncrementCounter(1)))];
+        let events = RecordedEvents::new(events[0].timestamp, events);
+        let payload = PayloadVersion::V1
+            .encode_with_session(&events, Some(&session()))
+            .unwrap();
+
+        let (_, converted) = transport.convert(&Bytes::from(payload)).unwrap();
+        let request = proto::ExportMetricsServiceRequest::decode(&converted[..]).unwrap();
+        assert_eq!(
+            resource_attributes(&request),
+            [
+                ("service.name", "app"),
+                ("session.id", "session"),
+                ("service.version", "1.2.3"),
+                ("user_agent.original", "Firefox 131 / Linux"),
+                ("page", "/player"),
+            ]
+        );
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// The subset of the [OTLP protobuf
+/// messages](https://github.com/open-telemetry/opentelemetry-proto/tree/main/opentelemetry/proto) needed to export
+/// metrics. Field numbers match the upstream definitions, unused fields are left out.
+pub mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ExportMetricsServiceRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub resource_metrics: Vec<ResourceMetrics>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ResourceMetrics {
+        #[prost(message, optional, tag = "1")]
+        pub resource: Option<Resource>,
+        #[prost(message, repeated, tag = "2")]
+        pub scope_metrics: Vec<ScopeMetrics>,
+        #[prost(string, tag = "3")]
+        pub schema_url: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Resource {
+        #[prost(message, repeated, tag = "1")]
+        pub attributes: Vec<KeyValue>,
+        #[prost(uint32, tag = "2")]
+        pub dropped_attributes_count: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ScopeMetrics {
+        #[prost(message, optional, tag = "1")]
+        pub scope: Option<InstrumentationScope>,
+        #[prost(message, repeated, tag = "2")]
+        pub metrics: Vec<Metric>,
+        #[prost(string, tag = "3")]
+        pub schema_url: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct InstrumentationScope {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub version: String,
+        #[prost(message, repeated, tag = "3")]
+        pub attributes: Vec<KeyValue>,
+        #[prost(uint32, tag = "4")]
+        pub dropped_attributes_count: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Metric {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub description: String,
+        #[prost(string, tag = "3")]
+        pub unit: String,
+        #[prost(oneof = "metric::Data", tags = "5, 7, 9")]
+        pub data: Option<metric::Data>,
+    }
+
+    pub mod metric {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Data {
+            #[prost(message, tag = "5")]
+            Gauge(super::Gauge),
+            #[prost(message, tag = "7")]
+            Sum(super::Sum),
+            #[prost(message, tag = "9")]
+            Histogram(super::Histogram),
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Gauge {
+        #[prost(message, repeated, tag = "1")]
+        pub data_points: Vec<NumberDataPoint>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Sum {
+        #[prost(message, repeated, tag = "1")]
+        pub data_points: Vec<NumberDataPoint>,
+        #[prost(enumeration = "AggregationTemporality", tag = "2")]
+        pub aggregation_temporality: i32,
+        #[prost(bool, tag = "3")]
+        pub is_monotonic: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Histogram {
+        #[prost(message, repeated, tag = "1")]
+        pub data_points: Vec<HistogramDataPoint>,
+        #[prost(enumeration = "AggregationTemporality", tag = "2")]
+        pub aggregation_temporality: i32,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum AggregationTemporality {
+        Unspecified = 0,
+        Delta = 1,
+        Cumulative = 2,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct NumberDataPoint {
+        #[prost(message, repeated, tag = "7")]
+        pub attributes: Vec<KeyValue>,
+        #[prost(fixed64, tag = "2")]
+        pub start_time_unix_nano: u64,
+        #[prost(fixed64, tag = "3")]
+        pub time_unix_nano: u64,
+        #[prost(uint32, tag = "8")]
+        pub flags: u32,
+        #[prost(oneof = "number_data_point::Value", tags = "4, 6")]
+        pub value: Option<number_data_point::Value>,
+    }
+
+    pub mod number_data_point {
+        #[derive(Clone, Copy, PartialEq, prost::Oneof)]
+        pub enum Value {
+            #[prost(double, tag = "4")]
+            AsDouble(f64),
+            #[prost(sfixed64, tag = "6")]
+            AsInt(i64),
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HistogramDataPoint {
+        #[prost(message, repeated, tag = "9")]
+        pub attributes: Vec<KeyValue>,
+        #[prost(fixed64, tag = "2")]
+        pub start_time_unix_nano: u64,
+        #[prost(fixed64, tag = "3")]
+        pub time_unix_nano: u64,
+        #[prost(fixed64, tag = "4")]
+        pub count: u64,
+        #[prost(double, optional, tag = "5")]
+        pub sum: Option<f64>,
+        #[prost(fixed64, repeated, tag = "6")]
+        pub bucket_counts: Vec<u64>,
+        #[prost(double, repeated, tag = "7")]
+        pub explicit_bounds: Vec<f64>,
+        #[prost(uint32, tag = "10")]
+        pub flags: u32,
+        #[prost(double, optional, tag = "11")]
+        pub min: Option<f64>,
+        #[prost(double, optional, tag = "12")]
+        pub max: Option<f64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct KeyValue {
+        #[prost(string, tag = "1")]
+        pub key: String,
+        #[prost(message, optional, tag = "2")]
+        pub value: Option<AnyValue>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AnyValue {
+        #[prost(oneof = "any_value::Value", tags = "1, 2, 3, 4")]
+        pub value: Option<any_value::Value>,
+    }
+
+    pub mod any_value {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Value {
+            #[prost(string, tag = "1")]
+            StringValue(String),
+            #[prost(bool, tag = "2")]
+            BoolValue(bool),
+            #[prost(int64, tag = "3")]
+            IntValue(i64),
+            #[prost(double, tag = "4")]
+            DoubleValue(f64),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use crate::Event;
+use std::{
+    fmt,
+    str::FromStr,
+};
+
+/// The priority of a batched event.
+///
+/// When a batch is full (see [crate::MetricsHttpSender::max_chunk_size]), the oldest [Priority::Low] event gets
+/// evicted. Without one, the oldest [Priority::Normal] event is evicted for a new normal event and a new low priority
+/// event is dropped. [Priority::Critical] events are never evicted and have their own limit, see
+/// [crate::MetricsHttpSender::max_critical_events]. Sampling rules sent by the server only apply to low priority
+/// events.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Noisy metrics that can be evicted, e.g. per-frame histograms.
+    Low,
+    /// The default priority of metrics events.
+    #[default]
+    Normal,
+    /// Events that must never be evicted. Metric descriptions always have this priority.
+    Critical,
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Priority::Low),
+            "normal" => Ok(Priority::Normal),
+            "critical" => Ok(Priority::Critical),
+            _ => Err(format!("unknown priority: {s}")),
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Priority::Low => f.write_str("low"),
+            Priority::Normal => f.write_str("normal"),
+            Priority::Critical => f.write_str("critical"),
+        }
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// Rules that assign a [Priority] to metrics events.
+///
+/// - [Event::Description]s are always [Priority::Critical].
+/// - If a [Self::label] is configured and the metric carries that label with a value of `low`, `normal` or `critical`,
+///   that priority is used.
+/// - Otherwise the longest matching [Self::prefix] rule decides.
+/// - Everything else is [Priority::Normal].
+#[derive(Debug, Default, Clone)]
+pub struct PriorityRules {
+    prefixes: Vec<(String, Priority)>,
+    label: Option<String>,
+}
+
+impl PriorityRules {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Assign `priority` to all metrics whose name starts with `prefix`.
+    pub fn prefix(mut self, prefix: impl ToString, priority: Priority) -> Self {
+        self.prefixes.push((prefix.to_string(), priority));
+        self
+    }
+
+    /// Read the priority of a metric from the label with the given key.
+    pub fn label(mut self, key: impl ToString) -> Self {
+        self.label = Some(key.to_string());
+        self
+    }
+
+    /// The priority of `event` according to these rules.
+    pub fn priority(&self, event: &Event) -> Priority {
+        let key = match event {
+            Event::Description { .. } => return Priority::Critical,
+            Event::Metric { key, .. } => key,
+        };
+
+        if let Some(label) = &self.label {
+            let priority = key
+                .labels()
+                .find(|l| l.key() == label)
+                .and_then(|l| l.value().parse().ok());
+            if let Some(priority) = priority {
+                return priority;
+            }
+        }
+
+        self.prefixes
+            .iter()
+            .filter(|(prefix, _)| key.name().starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, priority)| *priority)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        MetricOperation,
+        MetricType,
+    };
+    use metrics::{
+        Key,
+        Label,
+    };
+
+    fn metric(name: &'static str, labels: &[(&'static str, &'static str)]) -> Event {
+        let labels = labels
+            .iter()
+            .map(|(key, value)| Label::new(*key, *value))
+            .collect::<Vec<_>>();
+        Event::Metric {
+            key: Key::from_parts(name, labels),
+            op: MetricOperation::IncrementCounter(1),
+        }
+    }
+
+    #[test]
+    fn prefix_and_label_rules() {
+        let rules = PriorityRules::new()
+            .prefix("frame", Priority::Low)
+            .prefix("frame_errors", Priority::Critical)
+            .label("priority");
+
+        assert_eq!(rules.priority(&metric("requests", &[])), Priority::Normal);
+        assert_eq!(rules.priority(&metric("frame_time", &[])), Priority::Low);
+        assert_eq!(rules.priority(&metric("frame_errors_total", &[])), Priority::Critical);
+        assert_eq!(
+            rules.priority(&metric("frame_time", &[("priority", "normal")])),
+            Priority::Normal
+        );
+        assert_eq!(
+            rules.priority(&metric("requests", &[("priority", "urgent")])),
+            Priority::Normal
+        );
+        assert_eq!(
+            rules.priority(&Event::Description {
+                name: "frame_time".into(),
+                metric_type: MetricType::Histogram,
+                unit: None,
+                description: "".into(),
+            }),
+            Priority::Critical
+        );
+    }
+}
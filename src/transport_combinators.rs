@@ -0,0 +1,259 @@
+use crate::{
+    Transport,
+    TransportError,
+};
+use bytes::Bytes;
+use metrics_exporter_wasm_core::{
+    ExporterConfig,
+    PayloadMetadata,
+};
+use std::{
+    cell::{
+        Cell,
+        RefCell,
+    },
+    future::Future,
+};
+
+/// A [Transport] that tries a list of transports in order until one of them accepts the payload.
+///
+/// The transport that succeeded last is remembered and tried first for the next payload, so an unhealthy primary
+/// endpoint doesn't slow down every send.
+#[derive(Debug)]
+pub struct FailoverTransport<T> {
+    transports: Vec<T>,
+    healthy: Cell<usize>,
+}
+
+impl<T> FailoverTransport<T> {
+    pub fn new(transports: impl IntoIterator<Item = T>) -> Self {
+        Self {
+            transports: transports.into_iter().collect(),
+            healthy: Cell::new(0),
+        }
+    }
+}
+
+impl<T: Transport> Transport for FailoverTransport<T> {
+    fn enable_self_metrics(&mut self, self_metrics: bool) {
+        for transport in &mut self.transports {
+            transport.enable_self_metrics(self_metrics);
+        }
+    }
+
+    fn set_content_type(&mut self, content_type: &'static str) {
+        for transport in &mut self.transports {
+            transport.set_content_type(content_type);
+        }
+    }
+
+    async fn send(&self, payload: &Bytes) -> Result<(), TransportError> {
+        self.send_to_healthy(payload, None).await
+    }
+
+    async fn send_with_metadata(&self, payload: &Bytes, metadata: &PayloadMetadata) -> Result<(), TransportError> {
+        self.send_to_healthy(payload, Some(metadata)).await
+    }
+
+    fn take_remote_config(&self) -> Option<ExporterConfig> {
+        self.transports.iter().find_map(Transport::take_remote_config)
+    }
+
+    fn apply_remote_config(&mut self, config: &ExporterConfig) {
+        for transport in &mut self.transports {
+            transport.apply_remote_config(config);
+        }
+    }
+}
+
+impl<T: Transport> FailoverTransport<T> {
+    async fn send_to_healthy(&self, payload: &Bytes, metadata: Option<&PayloadMetadata>) -> Result<(), TransportError> {
+        let n = self.transports.len();
+        let start = self.healthy.get();
+        let mut last_err = None;
+        for i in 0..n {
+            let index = (start + i) % n;
+            match send(&self.transports[index], payload, metadata).await {
+                Ok(()) => {
+                    if index != start {
+                        debug!(%index, "metrics transport failed over");
+                    }
+                    self.healthy.set(index);
+                    return Ok(());
+                }
+                Err(err) => {
+                    warn!(?err, %index, "metrics transport failed, trying next one");
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| TransportError::network("no metrics transports configured")))
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// A [Transport] that delivers every payload to two transports concurrently, e.g. a collector and a debug endpoint.
+/// Nest it to fan out to more transports.
+///
+/// Only the result of the primary transport counts, failures of the secondary one are logged. When the sender retries a
+/// payload (identified by its [PayloadMetadata]), only the transports that didn't accept it yet get it again. Payloads
+/// sent without metadata go to both transports on every attempt.
+#[derive(Debug)]
+pub struct TeeTransport<A, B> {
+    primary: A,
+    secondary: B,
+    /// The last payload each transport accepted.
+    primary_delivered: RefCell<Option<PayloadMetadata>>,
+    secondary_delivered: RefCell<Option<PayloadMetadata>>,
+}
+
+impl<A, B> TeeTransport<A, B> {
+    pub fn new(primary: A, secondary: B) -> Self {
+        Self {
+            primary,
+            secondary,
+            primary_delivered: RefCell::new(None),
+            secondary_delivered: RefCell::new(None),
+        }
+    }
+}
+
+impl<A: Transport, B: Transport> Transport for TeeTransport<A, B> {
+    fn enable_self_metrics(&mut self, self_metrics: bool) {
+        self.primary.enable_self_metrics(self_metrics);
+        self.secondary.enable_self_metrics(self_metrics);
+    }
+
+    fn set_content_type(&mut self, content_type: &'static str) {
+        self.primary.set_content_type(content_type);
+        self.secondary.set_content_type(content_type);
+    }
+
+    fn send(&self, payload: &Bytes) -> impl Future<Output = Result<(), TransportError>> {
+        self.send_to_both(payload, None)
+    }
+
+    fn send_with_metadata(
+        &self,
+        payload: &Bytes,
+        metadata: &PayloadMetadata,
+    ) -> impl Future<Output = Result<(), TransportError>> {
+        self.send_to_both(payload, Some(metadata))
+    }
+
+    /// Only the primary transport steers the exporter.
+    fn take_remote_config(&self) -> Option<ExporterConfig> {
+        self.primary.take_remote_config()
+    }
+
+    fn apply_remote_config(&mut self, config: &ExporterConfig) {
+        self.primary.apply_remote_config(config);
+        self.secondary.apply_remote_config(config);
+    }
+}
+
+impl<A: Transport, B: Transport> TeeTransport<A, B> {
+    async fn send_to_both(&self, payload: &Bytes, metadata: Option<&PayloadMetadata>) -> Result<(), TransportError> {
+        let primary = send_once(&self.primary, &self.primary_delivered, payload, metadata);
+        let secondary = send_once(&self.secondary, &self.secondary_delivered, payload, metadata);
+        let (primary, secondary) = tokio::join!(primary, secondary);
+        if let Err(err) = secondary {
+            warn!(?err, "secondary metrics transport failed");
+        }
+        primary
+    }
+}
+
+/// Send unless `transport` already accepted the payload with this `metadata`.
+async fn send_once<T: Transport>(
+    transport: &T,
+    delivered: &RefCell<Option<PayloadMetadata>>,
+    payload: &Bytes,
+    metadata: Option<&PayloadMetadata>,
+) -> Result<(), TransportError> {
+    if metadata.is_some() && delivered.borrow().as_ref() == metadata {
+        return Ok(());
+    }
+    send(transport, payload, metadata).await?;
+    *delivered.borrow_mut() = metadata.cloned();
+    Ok(())
+}
+
+async fn send<T: Transport>(
+    transport: &T,
+    payload: &Bytes,
+    metadata: Option<&PayloadMetadata>,
+) -> Result<(), TransportError> {
+    match metadata {
+        Some(metadata) => transport.send_with_metadata(payload, metadata).await,
+        None => transport.send(payload).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryTransport;
+    use futures::executor::block_on;
+
+    fn metadata(sequence: u64) -> PayloadMetadata {
+        PayloadMetadata {
+            session_id: "session".to_string(),
+            nonce: "nonce".to_string(),
+            sequence,
+        }
+    }
+
+    #[test]
+    fn failover_in_order_and_sticky() {
+        let transports = [MemoryTransport::new(), MemoryTransport::new(), MemoryTransport::new()];
+        let failover = FailoverTransport::new(transports.clone());
+        let payload = Bytes::from_static(b"payload");
+
+        transports[0].fail_next(1);
+        block_on(failover.send(&payload)).unwrap();
+        assert_eq!(transports.each_ref().map(MemoryTransport::attempts), [1, 1, 0]);
+        assert_eq!(transports[1].payloads().len(), 1);
+
+        // The healthy transport is tried first from now on.
+        block_on(failover.send(&payload)).unwrap();
+        assert_eq!(transports.each_ref().map(MemoryTransport::attempts), [1, 2, 0]);
+
+        // Starting at the healthy transport, the list wraps around.
+        transports[1].fail_next(1);
+        transports[2].fail_next(1);
+        block_on(failover.send(&payload)).unwrap();
+        assert_eq!(transports.each_ref().map(MemoryTransport::attempts), [2, 3, 1]);
+        assert_eq!(transports[0].payloads().len(), 1);
+
+        transports.iter().for_each(|transport| transport.fail_next(1));
+        assert!(block_on(failover.send(&payload)).is_err());
+    }
+
+    #[test]
+    fn tee_ignores_secondary_failures() {
+        let (primary, secondary) = (MemoryTransport::new(), MemoryTransport::new());
+        let tee = TeeTransport::new(primary.clone(), secondary.clone());
+        secondary.fail_next(1);
+
+        block_on(tee.send_with_metadata(&Bytes::from_static(b"payload"), &metadata(0))).unwrap();
+        assert_eq!(primary.payloads().len(), 1);
+        assert_eq!(secondary.payloads().len(), 0);
+    }
+
+    #[test]
+    fn tee_retries_only_failed_transports() {
+        let (primary, secondary) = (MemoryTransport::new(), MemoryTransport::new());
+        let tee = TeeTransport::new(primary.clone(), secondary.clone());
+        let payload = Bytes::from_static(b"payload");
+        primary.fail_next(1);
+
+        assert!(block_on(tee.send_with_metadata(&payload, &metadata(0))).is_err());
+        block_on(tee.send_with_metadata(&payload, &metadata(0))).unwrap();
+        assert_eq!((primary.attempts(), secondary.attempts()), (2, 1));
+
+        block_on(tee.send_with_metadata(&payload, &metadata(1))).unwrap();
+        assert_eq!((primary.payloads().len(), secondary.payloads().len()), (2, 2));
+    }
+}
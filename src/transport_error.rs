@@ -0,0 +1,139 @@
+use metrics_exporter_wasm_core::util_time;
+use std::{
+    fmt,
+    io,
+    time::Duration,
+};
+
+/// Why a [crate::Transport] failed to send a payload.
+#[derive(Debug)]
+pub enum TransportError {
+    /// The request couldn't be sent or the connection failed.
+    Network(String),
+    /// The transport gave up waiting for the server.
+    Timeout,
+    /// The server responded with a non-2xx status.
+    Http(HttpError),
+    /// The server refused the payload without an HTTP status, e.g. over a WebSocket. Sending it again won't help.
+    Rejected(String),
+    /// The batch couldn't be encoded by the [crate::Encoder] or the transport.
+    Encode(io::Error),
+    /// The payload couldn't be compressed.
+    Compress(io::Error),
+    /// The request was aborted, e.g. because the page is unloading.
+    Aborted,
+}
+
+impl TransportError {
+    pub fn network(err: impl fmt::Display) -> Self {
+        TransportError::Network(err.to_string())
+    }
+
+    /// Network errors, timeouts and some HTTP errors (see [HttpError::is_retryable]) are worth retrying. Rejected
+    /// payloads, encoding and compression will fail again and aborted requests stay aborted.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            TransportError::Network(_) | TransportError::Timeout => true,
+            TransportError::Http(err) => err.is_retryable(),
+            TransportError::Rejected(_)
+            | TransportError::Encode(_)
+            | TransportError::Compress(_)
+            | TransportError::Aborted => false,
+        }
+    }
+
+    /// How long the server asked to wait before retrying.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            TransportError::Http(err) => err.retry_after,
+            _ => None,
+        }
+    }
+
+    /// The HTTP status, if the server responded.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            TransportError::Http(err) => Some(err.status),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Network(err) => write!(f, "network error: {err}"),
+            TransportError::Timeout => f.write_str("timed out"),
+            TransportError::Http(err) => err.fmt(f),
+            TransportError::Rejected(reason) => write!(f, "server rejected payload: {reason}"),
+            TransportError::Encode(err) => write!(f, "failed to encode payload: {err}"),
+            TransportError::Compress(err) => write!(f, "failed to compress payload: {err}"),
+            TransportError::Aborted => f.write_str("aborted"),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TransportError::Http(err) => Some(err),
+            TransportError::Encode(err) | TransportError::Compress(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<TransportError> for io::Error {
+    fn from(err: TransportError) -> Self {
+        let kind = match &err {
+            TransportError::Timeout => io::ErrorKind::TimedOut,
+            TransportError::Rejected(_) | TransportError::Encode(_) | TransportError::Compress(_) => {
+                io::ErrorKind::InvalidData
+            }
+            TransportError::Aborted => io::ErrorKind::Interrupted,
+            TransportError::Network(_) | TransportError::Http(_) => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+// -=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-
+
+/// A non-2xx response of the server.
+#[derive(Debug, Clone)]
+pub struct HttpError {
+    pub status: u16,
+    /// The parsed `Retry-After` header, if present.
+    pub retry_after: Option<Duration>,
+    pub body: String,
+}
+
+impl HttpError {
+    /// Server errors, `408 Request Timeout` and `429 Too Many Requests` are worth retrying, other client errors are
+    /// not.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.status, 408 | 429) || self.status >= 500
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "metrics endpoint responded with status={} {}",
+            self.status, self.body
+        )
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+/// `Retry-After` is either a number of seconds or an HTTP date.
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.to_utc() - util_time::now()).to_std().unwrap_or_default())
+}
@@ -0,0 +1,207 @@
+use crate::{
+    http_transport::{
+        EndpointDefined,
+        EndpointUndefined,
+    },
+    Transport,
+    TransportError,
+};
+use backon::{
+    ExponentialBuilder,
+    Retryable,
+};
+use bytes::Bytes;
+use futures::{
+    SinkExt as _,
+    StreamExt as _,
+};
+use gloo::net::websocket::{
+    futures::WebSocket,
+    Message,
+    State,
+};
+use metrics_exporter_wasm_core::{
+    PayloadVersion,
+    WEBSOCKET_ACK,
+};
+use std::{
+    future::Future,
+    time::Duration,
+};
+use tokio::sync::Mutex;
+
+/// A [Transport] that keeps a persistent WebSocket connection open and sends every payload as a binary frame.
+///
+/// The server must answer every payload frame with a text frame. [WEBSOCKET_ACK] marks success, any other text is
+/// treated as an error message. Only one payload is in flight at a time, so acks don't need to be correlated.
+///
+/// The connection is opened lazily on the first send and re-opened (with backoff) after it was closed or an error
+/// occurred.
+///
+/// WebSocket frames have no content type, the [PayloadVersion] of the encoder's content type is signaled with the
+/// WebSocket subprotocol of [PayloadVersion::websocket_protocol] instead. Servers that don't accept the subprotocol,
+/// like servers before payloads were versioned, fail the connection with [TransportError::Rejected].
+///
+/// Compression is not supported since there are no per-message headers to signal the content encoding.
+pub struct WebSocketTransport<T> {
+    timeout: Duration,
+    self_metrics: bool,
+    endpoint: T,
+    protocol: Option<&'static str>,
+    socket: Mutex<Option<WebSocket>>,
+}
+
+impl Default for WebSocketTransport<EndpointUndefined> {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            self_metrics: false,
+            endpoint: EndpointUndefined,
+            protocol: PayloadVersion::default().websocket_protocol(),
+            socket: Mutex::new(None),
+        }
+    }
+}
+
+impl WebSocketTransport<EndpointUndefined> {
+    /// Create a new WebSocket transport.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Set whether to emit internal metrics.
+    pub fn self_metrics(mut self, self_metrics: bool) -> Self {
+        self.self_metrics = self_metrics;
+        self
+    }
+
+    /// How long to wait for connecting, sending and the server ack before giving up.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set the WebSocket URL, e.g. `wss://example.com/receive-metrics-ws`.
+    pub fn endpoint(self, endpoint: impl ToString) -> WebSocketTransport<EndpointDefined> {
+        WebSocketTransport {
+            timeout: self.timeout,
+            self_metrics: self.self_metrics,
+            endpoint: EndpointDefined(endpoint.to_string()),
+            protocol: self.protocol,
+            socket: self.socket,
+        }
+    }
+}
+
+impl Transport for WebSocketTransport<EndpointDefined> {
+    fn enable_self_metrics(&mut self, self_metrics: bool) {
+        self.self_metrics = self_metrics;
+    }
+
+    fn set_content_type(&mut self, content_type: &'static str) {
+        self.protocol = PayloadVersion::from_content_type(Some(content_type))
+            .ok()
+            .and_then(PayloadVersion::websocket_protocol);
+    }
+
+    fn send(&self, payload: &Bytes) -> impl Future<Output = Result<(), TransportError>> {
+        let timeout = self.timeout;
+        let EndpointDefined(endpoint) = &self.endpoint;
+        let protocol = self.protocol;
+        let self_metrics = self.self_metrics;
+        let payload = payload.clone();
+
+        async move {
+            // Holding the lock for the whole round trip makes sure that acks arrive in order.
+            let mut socket = self.socket.lock().await;
+
+            let fut = async {
+                if !socket.as_ref().is_some_and(|ws| matches!(ws.state(), State::Open)) {
+                    *socket = Some(connect_with_backoff(endpoint, protocol).await?);
+                }
+                let ws = socket.as_mut().expect("socket connected");
+
+                ws.send(Message::Bytes(payload.to_vec()))
+                    .await
+                    .map_err(TransportError::network)?;
+
+                match ws.next().await {
+                    Some(Ok(Message::Text(text))) if text == WEBSOCKET_ACK => {}
+                    Some(Ok(Message::Text(text))) => {
+                        return Err(TransportError::Rejected(text));
+                    }
+                    Some(Ok(Message::Bytes(_))) => {
+                        return Err(TransportError::network("unexpected binary frame from server"));
+                    }
+                    Some(Err(err)) => return Err(TransportError::network(err)),
+                    None => return Err(TransportError::network("websocket closed before ack")),
+                }
+
+                if self_metrics {
+                    metrics::histogram!("metrics_exporter_compressed_payload_size").record(payload.len() as f64);
+                }
+
+                Ok(())
+            };
+
+            let result = tokio::select! {
+                biased;
+                res = fut => res,
+                _ = wasmtimer::tokio::sleep(timeout) => {
+                    Err(TransportError::Timeout)
+                }
+            };
+
+            if result.is_err() {
+                // Drop the connection, we can't know which ack the next frame would belong to.
+                *socket = None;
+            }
+
+            result
+        }
+    }
+}
+
+async fn connect_with_backoff(endpoint: &str, protocol: Option<&str>) -> Result<WebSocket, TransportError> {
+    let connect = || async { connect(endpoint, protocol).await };
+    connect
+        .retry(
+            ExponentialBuilder::new()
+                .with_max_times(3)
+                .with_factor(2.0)
+                .with_min_delay(Duration::from_millis(250))
+                .with_max_delay(Duration::from_secs(2)),
+        )
+        .when(TransportError::is_retryable)
+        .notify(|err: &TransportError, dur: Duration| {
+            debug!(?err, "failed to connect metrics websocket, retrying in {dur:?}");
+        })
+        .await
+}
+
+async fn connect(endpoint: &str, protocol: Option<&str>) -> Result<WebSocket, TransportError> {
+    let ws = match protocol {
+        Some(protocol) => WebSocket::open_with_protocol(endpoint, protocol),
+        None => WebSocket::open(endpoint),
+    };
+    let mut ws = ws.map_err(TransportError::network)?;
+    // Resolves once the socket left the connecting state.
+    futures::future::poll_fn(|cx| ws.poll_ready_unpin(cx))
+        .await
+        .map_err(TransportError::network)?;
+    if !matches!(ws.state(), State::Open) {
+        return Err(TransportError::network("failed to open metrics websocket"));
+    }
+    // Browsers accept a handshake without a subprotocol even if one was requested. The server would then
+    // decode the payloads as another version, don't send any.
+    let accepted = ws.protocol();
+    if let Some(protocol) = protocol {
+        if accepted != protocol {
+            // Dropping the socket closes it.
+            return Err(TransportError::Rejected(format!(
+                "server did not accept websocket protocol {protocol:?}, got {accepted:?}"
+            )));
+        }
+    }
+    Ok(ws)
+}
@@ -0,0 +1,112 @@
+//! Trains a zstd dictionary from captured, uncompressed metrics payloads of any `PayloadVersion`.
+//!
+//! Usage: `train-zstd-dictionary [--max-size BYTES] [--content-type TYPE] <OUTPUT> <PAYLOAD FILE OR DIRECTORY>...`
+
+use metrics_exporter_wasm_core::{
+    zstd_dictionary_id,
+    DecodeLimits,
+    PayloadVersion,
+};
+use std::{
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+
+const DEFAULT_MAX_SIZE: usize = 16 * 1024;
+
+fn main() -> io::Result<()> {
+    let mut max_size = DEFAULT_MAX_SIZE;
+    // Captured files have no content type, accept payloads of every version unless one is given.
+    let mut versions = vec![PayloadVersion::V0, PayloadVersion::V1];
+    let mut paths = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-size" => {
+                max_size = args
+                    .next()
+                    .and_then(|size| size.parse().ok())
+                    .ok_or_else(|| usage("--max-size expects a number of bytes"))?;
+            }
+            "--content-type" => {
+                let content_type = args
+                    .next()
+                    .ok_or_else(|| usage("--content-type expects the content type of the payloads"))?;
+                versions = vec![PayloadVersion::from_content_type(Some(&content_type))?];
+            }
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    if paths.len() < 2 {
+        return Err(usage("expected an output file and at least one payload"));
+    }
+    let output = paths.remove(0);
+
+    let mut samples = Vec::new();
+    for path in &paths {
+        collect_samples(path, &versions, &mut samples)?;
+    }
+    if samples.is_empty() {
+        return Err(usage("no valid payloads found"));
+    }
+
+    let total: usize = samples.iter().map(Vec::len).sum();
+    eprintln!("training on {} payloads ({total} bytes)", samples.len());
+
+    let dictionary = zstd::dict::from_samples(&samples, max_size)?;
+    std::fs::write(&output, &dictionary)?;
+
+    let id = zstd_dictionary_id(&dictionary).unwrap_or_default();
+    let compressed: usize = samples
+        .iter()
+        .map(|sample| zstd::bulk::Compressor::with_dictionary(3, &dictionary)?.compress(sample))
+        .map(|compressed| compressed.map(|compressed| compressed.len()))
+        .sum::<io::Result<_>>()?;
+    eprintln!(
+        "wrote dictionary {id} ({} bytes) to {}, compresses the samples to {:.1}% at level 3",
+        dictionary.len(),
+        output.display(),
+        compressed as f64 / total as f64 * 100.0
+    );
+
+    Ok(())
+}
+
+/// Adds the payload in `path`, or all payloads in the directory `path`. Files that don't decode in one of `versions`
+/// are skipped, training on compressed or otherwise unrelated data produces a useless dictionary.
+fn collect_samples(path: &Path, versions: &[PayloadVersion], samples: &mut Vec<Vec<u8>>) -> io::Result<()> {
+    if path.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            collect_samples(&entry?.path(), versions, samples)?;
+        }
+        return Ok(());
+    }
+
+    let data = std::fs::read(path)?;
+    let mut result = Ok(());
+    for version in versions {
+        result = version.decode(&data, &DecodeLimits::default()).map(drop);
+        if result.is_ok() {
+            break;
+        }
+    }
+    match result {
+        Ok(()) => samples.push(data),
+        Err(err) => eprintln!("skipping {}: {err}", path.display()),
+    }
+    Ok(())
+}
+
+const USAGE: &str =
+    "usage: train-zstd-dictionary [--max-size BYTES] [--content-type TYPE] <OUTPUT> <PAYLOAD FILE OR DIRECTORY>...";
+
+fn usage(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{msg}\n{USAGE}"))
+}